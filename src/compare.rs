@@ -0,0 +1,65 @@
+use std::{fs::Metadata, path::Path};
+
+use filetime::FileTime;
+
+use crate::{config::compare_mode::CompareMode, util::hash_file::hash_file};
+
+/// Size and modification time of a file; everything metadata-based comparisons look at.
+#[derive(Debug, Clone, Copy)]
+pub struct FileState {
+  pub bytes: u64,
+  pub mtime: FileTime,
+}
+
+impl From<&Metadata> for FileState {
+  fn from(metadata: &Metadata) -> Self {
+    Self {
+      bytes: metadata.len(),
+      mtime: FileTime::from_last_modification_time(metadata),
+    }
+  }
+}
+
+/// Result of comparing a source file to its destination equivalent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+  /// Destination is up to date.
+  Equal,
+  /// Destination needs to be copied.
+  Different,
+  /// Metadata is not enough to decide; content has to be compared.
+  Ambiguous,
+}
+
+/// Compares source and destination by metadata, according to the compare mode.
+pub fn compare_metadata(mode: CompareMode, src: &FileState, dst: &FileState) -> Comparison {
+  let newer = src.mtime > dst.mtime;
+  let size_differs = src.bytes != dst.bytes;
+  let different = match mode {
+    CompareMode::MtimeOrSize => newer || size_differs,
+    CompareMode::Mtime => newer,
+    CompareMode::Size | CompareMode::Checksum => size_differs,
+  };
+  if different {
+    Comparison::Different
+  } else if mode == CompareMode::Checksum {
+    // equal sizes can't tell anything about content
+    Comparison::Ambiguous
+  } else {
+    Comparison::Equal
+  }
+}
+
+/// Compares two files by content hash. A known destination hash (eg. from the index) saves reading the destination.
+/// Any read error counts as "different", so the file gets copied (or its copy fails visibly).
+/// Returns the content hash alongside, if both files are equal.
+pub fn compare_content(src: &Path, dst: &Path, dst_hash: Option<blake3::Hash>) -> (Comparison, Option<blake3::Hash>) {
+  let dst_hash = match dst_hash {
+    Some(hash) => Ok(hash),
+    None => hash_file(dst),
+  };
+  match (hash_file(src), dst_hash) {
+    (Ok(src_hash), Ok(dst_hash)) if src_hash == dst_hash => (Comparison::Equal, Some(src_hash)),
+    _ => (Comparison::Different, None),
+  }
+}
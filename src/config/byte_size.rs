@@ -0,0 +1,43 @@
+use serde::{Deserialize, Deserializer};
+use std::str::FromStr;
+
+/// A size in bytes; parsed from a plain number of bytes or a number with a binary unit suffix
+/// (`K`, `M`, `G`, `T`, optionally followed by `iB` or `B`), eg. `512M` or `1.5GiB`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let trimmed = s.trim();
+    let split = trimmed.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("Invalid size '{}'.", s))?;
+    let factor: u64 = match unit.trim().to_ascii_lowercase().trim_end_matches("ib").trim_end_matches('b') {
+      "" => 1,
+      "k" => 1024,
+      "m" => 1024 * 1024,
+      "g" => 1024 * 1024 * 1024,
+      "t" => 1024 * 1024 * 1024 * 1024,
+      _ => return Err(format!("Invalid size unit in '{}'; use K, M, G or T.", s))
+    };
+    Ok(ByteSize((number * factor as f64) as u64))
+  }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+  /// Accepts either a number of bytes or a string with unit (see `FromStr`).
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+      Bytes(u64),
+      Text(String),
+    }
+    match Raw::deserialize(deserializer)? {
+      Raw::Bytes(bytes) => Ok(ByteSize(bytes)),
+      Raw::Text(text) => text.parse().map_err(serde::de::Error::custom),
+    }
+  }
+}
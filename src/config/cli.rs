@@ -1,7 +1,12 @@
-use clap::{Parser, ValueHint};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueHint};
 use serde::Deserialize;
 use std::path::PathBuf;
 
+use crate::config::{
+  byte_size::ByteSize, compare_mode::CompareMode, preserve::Attribute, retention::Retention, rule_args::RuleArgs,
+  symlink_mode::SymlinkMode, sync_mode::SyncMode
+};
+
 #[derive(Debug, Parser, Deserialize)]
 #[command(name = "baccy", version, about = "Efficient and informative directory sync")]
 pub struct Arguments {
@@ -18,107 +23,116 @@ pub struct Arguments {
   #[arg(index = 2, value_hint = ValueHint::DirPath)]
   pub target: Option<PathBuf>,
 
-  /// Exclude all directories (recursively) that have an exactly matching name.
-  /// Accepts one or multiple values.
-  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
-  #[arg(
-    long = "exclude-dirs",
-    alias = "xd",
-    short = 'd',
-    num_args = 0.. // 0 makes it optional, 1.. would have been "required with at least 1 argument"
-  )]
+  /// Name of the operation (JSON only); used to select operations in subcommands.
+  #[arg(skip)]
   #[serde(default)]
-  pub exclude_dirs: Vec<String>,
+  pub name: Option<String>,
+
+  /// Exclusion, inclusion and force-inclusion rules.
+  #[command(flatten)]
+  #[serde(flatten)]
+  pub rules: RuleArgs,
 
-  /// Exclude all files having an exactly matching name.
-  /// Accepts one or multiple values.
-  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  /// Defines how the target is kept in sync with the source.
+  /// "mirror" makes the target directly contain a copy of the source.
+  /// "snapshot" creates a new, timestamped directory in the target on each run; files unchanged since the
+  /// previous snapshot are hard-linked instead of copied. Nothing is deleted from previous snapshots.
+  /// If in JSON-config mode: sets mode for all operations in JSON, overriding per-operation setting.
   #[arg(
-    long = "exclude-files",
-    alias = "xf",
-    short = 'f',
-    num_args = 0..
+    long = "mode",
+    value_enum
   )]
-  #[serde(default)]
-  pub exclude_files: Vec<String>,
+  #[serde(default)] // defaults to None => mirror
+  pub mode: Option<SyncMode>,
+
+  /// Retention rules for snapshots; applied after each snapshot-mode run and by "prune".
+  #[command(flatten)]
+  #[serde(flatten)]
+  pub retention: Retention,
 
-  /// Exclude all paths matching a pattern.
-  /// Accepts one or multiple glob-like patterns ('*', '**', '?' - eg: 'src/**/*.txt').
-  /// Patterns are matched relative to the source directory.
-  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  /// Defines how files are compared to decide whether they need to be copied.
+  /// "checksum" compares content hashes of files with equal sizes, ignoring modification times.
+  /// If in JSON-config mode: sets compare mode for all operations in JSON, overriding per-operation setting.
   #[arg(
-    long = "exclude-patterns",
-    alias = "xp",
-    short = 'p',
-    num_args = 0..
+    long = "compare",
+    alias = "cmp",
+    value_enum
   )]
-  #[serde(default)]
-  pub exclude_patterns: Vec<String>,
+  #[serde(default)] // defaults to None => mtime-or-size
+  pub compare: Option<CompareMode>,
 
-  /// Include only directories (recursively) having an exactly matching name.
-  /// Accepts one or multiple values. This is checked after exclusions.
-  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  /// Defines how symlinks in the source are synced.
+  /// "preserve" recreates them in the target, "follow" copies what they point to, "skip" ignores them,
+  /// "follow-safe" follows them only if they point into the source (others are skipped with a warning).
+  /// If in JSON-config mode: sets symlink handling for all operations in JSON, overriding per-operation setting.
   #[arg(
-    long = "include-dirs",
-    alias = "id",
-    num_args = 0.. // 0 makes it optional, 1.. would have been "required with at least 1 argument"
+    long = "symlinks",
+    alias = "sl",
+    value_enum
   )]
-  #[serde(default)]
-  pub include_dirs: Vec<String>,
+  #[serde(default)] // defaults to None => preserve
+  pub symlinks: Option<SymlinkMode>,
 
-  /// Include only files having an exactly matching name.
-  /// Accepts one or multiple values. This is checked after exclusions.
-  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  /// Metadata to preserve in the target, in addition to modification times: "mode" (permissions), "owner"
+  /// (user and group, only as root), "times" (access times and directory modification times), "xattrs"
+  /// (extended attributes) and "acls" (POSIX ACLs). Defaults to "mode".
+  /// Files whose preserved metadata changed without a content change only get their metadata updated.
+  /// If in JSON-config mode: sets preserved metadata for all operations in JSON, overriding per-operation setting.
   #[arg(
-    long = "include-files",
-    alias = "if",
-    num_args = 0.. // 0 makes it optional, 1.. would have been "required with at least 1 argument"
+    long = "preserve",
+    alias = "pr",
+    num_args = 1..,
+    value_enum
   )]
-  #[serde(default)]
-  pub include_files: Vec<String>,
+  #[serde(default)] // defaults to None => mode
+  pub preserve: Option<Vec<Attribute>>,
 
-  /// Include only paths matching a pattern.
-  /// Accepts one or multiple glob-like patterns ('*', '**', '?' - eg: 'src/**/*.txt').
-  /// Patterns are matched relative to the source directory. This is checked after exclusions.
-  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  /// Keeps an index of all files in the target (in "<target>/.baccy/index"), written after each run.
+  /// The scanner compares against the index instead of reading the target's file metadata, and
+  /// checksum comparisons don't have to hash unchanged target files again.
+  /// Files changed in the target outside of baccy will not be detected while indexed.
+  /// If in JSON-config mode: sets index for all operations in JSON, overriding per-operation setting.
   #[arg(
-    long = "include-patterns",
-    alias = "ip",
-    num_args = 0..
+    long = "index",
+    alias = "ix",
+    action // = false if not given, true if present
   )]
-  #[serde(default)]
-  pub include_patterns: Vec<String>,
+  #[serde(default)] // defaults to false
+  pub index: bool,
 
-  /// Forces inclusion of matching directory names, overriding all exclude and include rules.
+  /// Re-reads every copied file from the target after copying and compares it to a hash computed while reading
+  /// the source. Mismatching files are copied again once, then reported as errors (exit code 1).
+  /// If in JSON-config mode: sets verify for all operations in JSON, overriding per-operation setting.
   #[arg(
-    long = "force-include-dirs",
-    alias = "fid",
-    num_args = 0.. // 0 makes it optional, 1.. would have been "required with at least 1 argument"
+    long = "verify",
+    alias = "vf",
+    action // = false if not given, true if present
   )]
-  #[serde(default)]
-  pub force_include_dirs: Vec<String>,
+  #[serde(default)] // defaults to false
+  pub verify: bool,
 
-  /// Forces inclusion of matching file names, overriding all exclude and include rules.
-  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  /// Files of at least this size (eg. "1G") that already exist in the target are updated in place,
+  /// writing only changed blocks (found using rolling checksums) instead of copying the whole file.
+  /// Not used with a backup dir or in snapshot mode, where the existing file must be kept.
+  /// If in JSON-config mode: sets delta threshold for all operations in JSON, overriding per-operation setting.
   #[arg(
-    long = "force-include-files",
-    alias = "fif",
-    num_args = 0.. // 0 makes it optional, 1.. would have been "required with at least 1 argument"
+    long = "delta-threshold",
+    alias = "dt"
   )]
-  #[serde(default)]
-  pub force_include_files: Vec<String>,
+  #[serde(default)] // defaults to None => no delta updates
+  pub delta_threshold: Option<ByteSize>,
 
-  /// Forces inclusion of paths matching a pattern, overriding all exclude and include rules.
-  /// Accepts one or multiple glob-like patterns ('*', '**', '?' - eg: 'src/**/*.txt').
-  /// Patterns are matched relative to the source directory.
-  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  /// Instead of deleting or overwriting files in the target, moves them into "<backup dir>/<timestamp>/",
+  /// keeping their relative paths. Relative paths are relative to the target; the backup directory is never
+  /// deleted by mirroring.
+  /// If in JSON-config mode: sets backup dir for all operations in JSON, overriding per-operation setting.
   #[arg(
-    long = "force-include-patterns",
-    alias = "fip",
-    num_args = 0..
+    long = "backup-dir",
+    alias = "bd",
+    value_hint = ValueHint::DirPath
   )]
   #[serde(default)]
-  pub force_include_patterns: Vec<String>,
+  pub backup_dir: Option<PathBuf>,
 
   /// Skips the "delete files from target not present in source" step.
   /// If in JSON-config mode: sets no-delete for all operations in JSON, overriding per-operation setting.
@@ -129,6 +143,18 @@ pub struct Arguments {
   )] 
   #[serde(default)] // defaults to false
   pub no_delete: bool,
+
+  /// Runs the operation without modifying the target: files and directories that would be copied
+  /// or deleted are printed instead.
+  /// If in JSON-config mode: sets dry-run for all operations in JSON, overriding per-operation setting.
+  #[arg(
+    long = "dry-run",
+    alias = "dr",
+    short = 'n',
+    action // = false if not given, true if present
+  )]
+  #[serde(default)] // defaults to false
+  pub dry_run: bool,
   
   /// Prints the names of copied and deleted files during processing.
   /// If in JSON-config-mode: sets log-files for all operations in JSON, overriding per-operation setting.
@@ -150,17 +176,152 @@ pub struct Arguments {
   )]
   #[serde(default)] // defaults to false
   pub log_rules: bool,
+
+  /// Set by "restore" only: the rules of the restored operation, with the restored path relative to its source.
+  /// Files in the target these rules don't include were never backed up, so they're not deleted.
+  #[arg(skip)]
+  #[serde(skip)]
+  pub protected_rules: Option<(RuleArgs, PathBuf)>,
 }
 
 impl Arguments {
   pub fn is_json_config(&self) -> bool {
-    match &self.target {
-      Some(_) => false,
-      None => true
-    }
+    self.target.is_none()
   }
 }
 
+#[derive(Debug, Subcommand)]
+pub enum Command {
+  /// Deletes old snapshots of snapshot-mode operations in a JSON config, according to their retention rules.
+  Prune {
+    /// JSON configuration file.
+    #[arg(value_parser = args_validate_existing_path, value_hint = ValueHint::FilePath)]
+    config: PathBuf,
+
+    /// Only prunes the operation with this name.
+    #[arg(long = "operation", short = 'o')]
+    operation: Option<String>,
+
+    /// Prints which snapshots would be deleted, without deleting them.
+    #[arg(long = "dry-run", alias = "dr", short = 'n', action)]
+    dry_run: bool,
+  },
+
+  /// Compares targets to their sources without modifying anything; reports missing, differing and extra files.
+  /// Exits with 0 if in sync, 3 if out of sync, 4 if files couldn't be read.
+  Verify {
+    /// Source directory, or JSON configuration file if no target is given.
+    #[arg(value_parser = args_validate_existing_path, value_hint = ValueHint::AnyPath)]
+    source: PathBuf,
+
+    /// Target directory to verify. If not given, the first argument is interpreted as JSON config.
+    #[arg(value_hint = ValueHint::DirPath)]
+    target: Option<PathBuf>,
+
+    /// Only verifies the operation with this name (JSON config only).
+    #[arg(long = "operation", short = 'o')]
+    operation: Option<String>,
+
+    /// Compares content hashes of all files with equal sizes, instead of using the compare mode.
+    #[arg(long = "checksum", alias = "cs", action)]
+    checksum: bool,
+
+    /// Prints a machine-readable JSON report instead.
+    #[arg(long = "json", action)]
+    json: bool,
+  },
+
+  /// Shows what a sync would change, without modifying anything: new, modified, deleted and excluded files,
+  /// grouped by directory.
+  Diff {
+    /// Source directory, or JSON configuration file if no target is given.
+    #[arg(value_parser = args_validate_existing_path, value_hint = ValueHint::AnyPath)]
+    source: PathBuf,
+
+    /// Target directory to compare to. If not given, the first argument is interpreted as JSON config.
+    #[arg(value_hint = ValueHint::DirPath)]
+    target: Option<PathBuf>,
+
+    /// Only compares the operation with this name (JSON config only).
+    #[arg(long = "operation", short = 'o')]
+    operation: Option<String>,
+  },
+
+  /// Shows which rules of the operations in a JSON config match a path, where they are defined (CLI, global
+  /// or operation), and whether the path is excluded, included or force-included.
+  Explain {
+    /// JSON configuration file.
+    #[arg(value_parser = args_validate_existing_path, value_hint = ValueHint::FilePath)]
+    config: PathBuf,
+
+    /// Path of a file; relative to the source, or a path inside the source. Doesn't need to exist.
+    #[arg(value_hint = ValueHint::AnyPath)]
+    path: PathBuf,
+
+    /// Only explains the operation with this name.
+    #[arg(long = "operation", short = 'o')]
+    operation: Option<String>,
+
+    /// Rules given on the command line, as they would be for a sync.
+    #[command(flatten)]
+    rules: Box<RuleArgs>,
+  },
+
+  /// Syncs backups of the operations in a JSON config back to their sources (or another directory).
+  /// Never deletes anything unless "--delete" is given.
+  Restore {
+    /// JSON configuration file.
+    #[arg(value_parser = args_validate_existing_path, value_hint = ValueHint::FilePath)]
+    config: PathBuf,
+
+    /// Only restores the operation with this name.
+    #[arg(long = "operation", short = 'o')]
+    operation: Option<String>,
+
+    /// Only restores this file or directory; relative to the source, or a path inside the source.
+    #[arg(long = "path", value_hint = ValueHint::AnyPath)]
+    path: Option<PathBuf>,
+
+    /// Restores into this directory instead of the operation's source. Needs a single operation.
+    #[arg(long = "to", value_hint = ValueHint::DirPath)]
+    to: Option<PathBuf>,
+
+    /// Restores from the snapshot with this name instead of the newest one (snapshot mode only).
+    #[arg(long = "snapshot", alias = "snap")]
+    snapshot: Option<String>,
+
+    /// Deletes files from the restored directory that are not in the backup.
+    #[arg(long = "delete", action)]
+    delete: bool,
+
+    /// Prints which files would be restored, without modifying anything.
+    #[arg(long = "dry-run", alias = "dr", short = 'n', action)]
+    dry_run: bool,
+  },
+}
+
+/// What to do, as given on the command line.
+pub enum Invocation {
+  /// No subcommand: sync (source and target, or JSON config).
+  Sync(Box<Arguments>),
+  Command(Box<Command>),
+}
+
+/// Parses the command line. Subcommands take precedence over the positional arguments of a sync:
+/// a source directory named like a subcommand has to be given as eg. `./diff`, or after `--`.
+pub fn parse() -> Invocation {
+  let mut matches = Command::augment_subcommands(Arguments::command())
+    .args_conflicts_with_subcommands(true)
+    .subcommand_negates_reqs(true)
+    .get_matches();
+  let invocation = if matches.subcommand().is_some() {
+    Command::from_arg_matches_mut(&mut matches).map(|command| Invocation::Command(Box::new(command)))
+  } else {
+    Arguments::from_arg_matches_mut(&mut matches).map(|args| Invocation::Sync(Box::new(args)))
+  };
+  invocation.unwrap_or_else(|err| err.exit())
+}
+
 
 fn args_validate_existing_path(s: &str) -> Result<PathBuf, String> {
   let path = PathBuf::from(s);
@@ -0,0 +1,17 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Defines how source and destination files are compared to decide whether a file needs to be copied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompareMode {
+  /// Copy if the source is newer or file sizes differ.
+  #[default]
+  MtimeOrSize,
+  /// Copy if the source is newer.
+  Mtime,
+  /// Copy if file sizes differ.
+  Size,
+  /// Copy if file sizes or content hashes differ.
+  Checksum,
+}
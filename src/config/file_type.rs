@@ -0,0 +1,51 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::fs;
+
+/// Type of a directory entry (other than a directory), for file type rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileType {
+  /// Regular file.
+  File,
+  /// Symbolic link (not followed).
+  Symlink,
+  /// Named pipe (Unix only).
+  Fifo,
+  /// Unix domain socket (Unix only).
+  Socket,
+  /// Block device (Unix only).
+  BlockDevice,
+  /// Character device (Unix only).
+  CharDevice,
+}
+
+impl FileType {
+  /// Returns the type of an entry, if it's not a directory.
+  pub fn of(file_type: fs::FileType) -> Option<Self> {
+    #[cfg(unix)]
+    use std::os::unix::fs::FileTypeExt;
+
+    if file_type.is_file() { return Some(FileType::File) }
+    if file_type.is_symlink() { return Some(FileType::Symlink) }
+    #[cfg(unix)]
+    {
+      if file_type.is_fifo() { return Some(FileType::Fifo) }
+      if file_type.is_socket() { return Some(FileType::Socket) }
+      if file_type.is_block_device() { return Some(FileType::BlockDevice) }
+      if file_type.is_char_device() { return Some(FileType::CharDevice) }
+    }
+    None
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      FileType::File => "file",
+      FileType::Symlink => "symlink",
+      FileType::Fifo => "fifo",
+      FileType::Socket => "socket",
+      FileType::BlockDevice => "block-device",
+      FileType::CharDevice => "char-device",
+    }
+  }
+}
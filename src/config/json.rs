@@ -1,50 +1,30 @@
+use std::{fs, path::Path, process};
+
 use serde::Deserialize;
 
-use crate::config::cli::Arguments;
+use crate::config::{cli::Arguments, rule_args::RuleArgs};
 
 #[derive(Debug, Deserialize)]
 pub struct JSONConfig {
-  /// Exclude exactly matching directory names globally (for all operations).
-  #[serde(default)]
-  pub exclude_dirs: Vec<String>,
-
-  /// Exclude exactly matching file names globally (for all operations).
-  #[serde(default)]
-  pub exclude_files: Vec<String>,
-
-  /// Exclude matching patterns globally (for all operations).
-  #[serde(default)]
-  pub exclude_patterns: Vec<String>,
-
-  /// Include only exactly matching directory names globally (for all operations).
-  /// Checked after exclusions.
-  #[serde(default)]
-  pub include_dirs: Vec<String>,
+  /// Exclusion, inclusion and force-inclusion rules applied globally (for all operations);
+  /// merged with each operation's rules.
+  #[serde(flatten)]
+  pub rules: RuleArgs,
 
-  /// Include only exactly matching file names globally (for all operations).
-  /// Checked after exclusions.
-  #[serde(default)]
-  pub include_files: Vec<String>,
-
-  /// Include only matching patterns globally (for all operations).
-  /// Checked after exclusions.
-  #[serde(default)]
-  pub include_patterns: Vec<String>,
-
-  /// Forces inclusion of exactly matching directory names globally (for all operations),
-  /// overriding all exclude and include rules.
+  /// Sets "keep an index of target files" (for all operations).
+  /// Sets all operations to "true" if set, no effect if set to "false".
   #[serde(default)]
-  pub force_include_dirs: Vec<String>,
+  pub index: bool,
 
-  /// Forces inclusion of exactly matching file names globally (for all operations),
-  /// overriding all exclude and include rules.
+  /// Sets "verify copied files" (for all operations).
+  /// Sets all operations to "true" if set, no effect if set to "false".
   #[serde(default)]
-  pub force_include_files: Vec<String>,
+  pub verify: bool,
 
-  /// Forces inclusion of matching patterns globally (for all operations),
-  /// overriding all exclude and include rules.
+  /// Sets "dry run, don't modify targets" (for all operations).
+  /// Sets all operations to "true" if set, no effect if set to "false".
   #[serde(default)]
-  pub force_include_patterns: Vec<String>,
+  pub dry_run: bool,
 
   /// Sets "print files copied and deleted" (for all operations).
   /// Sets all operations to "true" if set, no effect if set to "false".
@@ -72,4 +52,35 @@ pub struct JSONConfig {
   /// Defines sync operations to run.
   #[serde(default)]
   pub operations: Vec<Arguments>,
-}
\ No newline at end of file
+}
+
+impl JSONConfig {
+  /// Reads and parses a JSON config file. Exits the program on failure.
+  pub fn read(path: &Path) -> Self {
+    let config = fs::read_to_string(path).unwrap_or_else(|err| {
+      eprintln!("Failed to read config file '{}': {}", path.display(), err);
+      process::exit(1);
+    });
+    serde_json::from_str::<JSONConfig>(&config).unwrap_or_else(|err| {
+      eprintln!("Failed to parse JSON config: {}", err);
+      process::exit(1);
+    })
+  }
+
+  /// Takes the operations out of the config, with the global rules merged into each operation's rules.
+  /// If a name is given, only the operation with that name is returned; exits the program if there's none.
+  pub fn take_operations(&mut self, name: Option<&str>) -> Vec<Arguments> {
+    let operations: Vec<Arguments> = std::mem::take(&mut self.operations).into_iter()
+      .filter(|op| name.is_none() || op.name.as_deref() == name)
+      .map(|mut op| {
+        op.rules.merge(&self.rules);
+        op
+      })
+      .collect();
+    if let Some(name) = name && operations.is_empty() {
+      eprintln!("No operation named '{}' in config.", name);
+      process::exit(1);
+    }
+    operations
+  }
+}
@@ -1,2 +1,11 @@
+pub mod byte_size;
 pub mod cli;
-pub mod json;
\ No newline at end of file
+pub mod compare_mode;
+pub mod file_type;
+pub mod json;
+pub mod preserve;
+pub mod retention;
+pub mod rule_args;
+pub mod symlink_mode;
+pub mod sync_mode;
+pub mod time_bound;
@@ -0,0 +1,48 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// File metadata that can be preserved in the target, in addition to the modification time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Attribute {
+  /// Permission bits (only the read-only flag on Windows).
+  Mode,
+  /// User and group; only when running as root (Unix).
+  Owner,
+  /// Access times, and modification times of directories.
+  Times,
+  /// Extended attributes, except ACLs (Unix).
+  Xattrs,
+  /// POSIX ACLs, stored as extended attributes (Linux).
+  Acls,
+}
+
+/// Which metadata is preserved; modification times of files are always preserved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Preserve {
+  pub mode: bool,
+  pub owner: bool,
+  pub times: bool,
+  pub xattrs: bool,
+  pub acls: bool,
+}
+
+impl Preserve {
+  /// Preserves the given attributes; only the mode if none are given.
+  pub fn new(attributes: Option<&[Attribute]>) -> Self {
+    let attributes = attributes.unwrap_or(&[Attribute::Mode]);
+    Self {
+      mode: attributes.contains(&Attribute::Mode),
+      owner: attributes.contains(&Attribute::Owner),
+      times: attributes.contains(&Attribute::Times),
+      xattrs: attributes.contains(&Attribute::Xattrs),
+      acls: attributes.contains(&Attribute::Acls),
+    }
+  }
+}
+
+impl Default for Preserve {
+  fn default() -> Self {
+    Self::new(None)
+  }
+}
@@ -0,0 +1,49 @@
+use clap::Args;
+use serde::Deserialize;
+
+/// Defines which snapshots are kept when pruning.
+/// Rules are combined: a snapshot is kept if any rule keeps it. The newest snapshot is always kept.
+#[derive(Debug, Default, Clone, Args, Deserialize)]
+pub struct Retention {
+  /// Keeps the newest n snapshots.
+  #[arg(long = "keep-last")]
+  #[serde(default)]
+  pub keep_last: Option<usize>,
+
+  /// Keeps the newest snapshot of each of the last n hours that have snapshots.
+  #[arg(long = "keep-hourly")]
+  #[serde(default)]
+  pub keep_hourly: Option<usize>,
+
+  /// Keeps the newest snapshot of each of the last n days that have snapshots.
+  #[arg(long = "keep-daily")]
+  #[serde(default)]
+  pub keep_daily: Option<usize>,
+
+  /// Keeps the newest snapshot of each of the last n (ISO) weeks that have snapshots.
+  #[arg(long = "keep-weekly")]
+  #[serde(default)]
+  pub keep_weekly: Option<usize>,
+
+  /// Keeps the newest snapshot of each of the last n months that have snapshots.
+  #[arg(long = "keep-monthly")]
+  #[serde(default)]
+  pub keep_monthly: Option<usize>,
+
+  /// Keeps the newest snapshot of each of the last n years that have snapshots.
+  #[arg(long = "keep-yearly")]
+  #[serde(default)]
+  pub keep_yearly: Option<usize>,
+}
+
+impl Retention {
+  /// Returns true if any retention rule is set. Without rules, nothing is pruned.
+  pub fn is_set(&self) -> bool {
+    self.keep_last.is_some()
+    || self.keep_hourly.is_some()
+    || self.keep_daily.is_some()
+    || self.keep_weekly.is_some()
+    || self.keep_monthly.is_some()
+    || self.keep_yearly.is_some()
+  }
+}
@@ -0,0 +1,262 @@
+use clap::Args;
+use serde::Deserialize;
+
+use crate::config::{byte_size::ByteSize, file_type::FileType, time_bound::TimeBound};
+
+/// Exclusion, inclusion and force-inclusion rules, as given on the command line or in a JSON config.
+#[derive(Debug, Default, Clone, Args, Deserialize)]
+pub struct RuleArgs {
+  /// Exclude all directories (recursively) that have an exactly matching name.
+  /// Accepts one or multiple values.
+  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  #[arg(
+    long = "exclude-dirs",
+    alias = "xd",
+    short = 'd',
+    num_args = 0.. // 0 makes it optional, 1.. would have been "required with at least 1 argument"
+  )]
+  #[serde(default)]
+  pub exclude_dirs: Vec<String>,
+
+  /// Exclude all files having an exactly matching name.
+  /// Accepts one or multiple values.
+  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  #[arg(
+    long = "exclude-files",
+    alias = "xf",
+    short = 'f',
+    num_args = 0..
+  )]
+  #[serde(default)]
+  pub exclude_files: Vec<String>,
+
+  /// Exclude all paths matching a pattern.
+  /// Accepts one or multiple glob-like patterns ('*', '**', '?' - eg: 'src/**/*.txt').
+  /// Patterns are matched relative to the source directory.
+  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  #[arg(
+    long = "exclude-patterns",
+    alias = "xp",
+    short = 'p',
+    num_args = 0..
+  )]
+  #[serde(default)]
+  pub exclude_patterns: Vec<String>,
+
+  /// Exclude all paths matching a regular expression (eg: '~\$.*\.docx$').
+  /// Accepts one or multiple values. Expressions are matched against the path relative to the source directory,
+  /// with '/' as separator; they match anywhere in the path unless anchored with '^' and '$'.
+  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  #[arg(
+    long = "exclude-regex",
+    alias = "xr",
+    num_args = 0..
+  )]
+  #[serde(default)]
+  pub exclude_regex: Vec<String>,
+
+  /// Include only directories (recursively) having an exactly matching name.
+  /// Accepts one or multiple values. This is checked after exclusions.
+  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  #[arg(
+    long = "include-dirs",
+    alias = "id",
+    num_args = 0.. // 0 makes it optional, 1.. would have been "required with at least 1 argument"
+  )]
+  #[serde(default)]
+  pub include_dirs: Vec<String>,
+
+  /// Include only files having an exactly matching name.
+  /// Accepts one or multiple values. This is checked after exclusions.
+  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  #[arg(
+    long = "include-files",
+    alias = "if",
+    num_args = 0.. // 0 makes it optional, 1.. would have been "required with at least 1 argument"
+  )]
+  #[serde(default)]
+  pub include_files: Vec<String>,
+
+  /// Include only paths matching a pattern.
+  /// Accepts one or multiple glob-like patterns ('*', '**', '?' - eg: 'src/**/*.txt').
+  /// Patterns are matched relative to the source directory. This is checked after exclusions.
+  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  #[arg(
+    long = "include-patterns",
+    alias = "ip",
+    num_args = 0..
+  )]
+  #[serde(default)]
+  pub include_patterns: Vec<String>,
+
+  /// Include only paths matching a regular expression.
+  /// Accepts one or multiple values. Expressions are matched against the path relative to the source directory,
+  /// with '/' as separator. This is checked after exclusions.
+  /// In JSON-config-mode, this will be merged with the global includes defined in the JSON.
+  #[arg(
+    long = "include-regex",
+    alias = "ir",
+    num_args = 0..
+  )]
+  #[serde(default)]
+  pub include_regex: Vec<String>,
+
+  /// Forces inclusion of matching directory names, overriding all exclude and include rules.
+  #[arg(
+    long = "force-include-dirs",
+    alias = "fid",
+    num_args = 0.. // 0 makes it optional, 1.. would have been "required with at least 1 argument"
+  )]
+  #[serde(default)]
+  pub force_include_dirs: Vec<String>,
+
+  /// Forces inclusion of matching file names, overriding all exclude and include rules.
+  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  #[arg(
+    long = "force-include-files",
+    alias = "fif",
+    num_args = 0.. // 0 makes it optional, 1.. would have been "required with at least 1 argument"
+  )]
+  #[serde(default)]
+  pub force_include_files: Vec<String>,
+
+  /// Forces inclusion of paths matching a pattern, overriding all exclude and include rules.
+  /// Accepts one or multiple glob-like patterns ('*', '**', '?' - eg: 'src/**/*.txt').
+  /// Patterns are matched relative to the source directory.
+  /// In JSON-config-mode, this will be merged with the global excludes defined in the JSON.
+  #[arg(
+    long = "force-include-patterns",
+    alias = "fip",
+    num_args = 0..
+  )]
+  #[serde(default)]
+  pub force_include_patterns: Vec<String>,
+
+  /// Forces inclusion of paths matching a regular expression, overriding all exclude and include rules.
+  /// Expressions are matched against the path relative to the source directory, with '/' as separator.
+  /// In JSON-config-mode, this will be merged with the global force-includes defined in the JSON.
+  #[arg(
+    long = "force-include-regex",
+    alias = "fir",
+    num_args = 0..
+  )]
+  #[serde(default)]
+  pub force_include_regex: Vec<String>,
+
+  /// Excludes files larger than this size (eg. "100M").
+  /// In JSON-config-mode, the smallest of the CLI, global and per-operation values applies.
+  #[arg(
+    long = "max-size",
+    alias = "maxs"
+  )]
+  #[serde(default)]
+  pub max_size: Option<ByteSize>,
+
+  /// Excludes files smaller than this size (eg. "1K").
+  /// In JSON-config-mode, the largest of the CLI, global and per-operation values applies.
+  #[arg(
+    long = "min-size",
+    alias = "mins"
+  )]
+  #[serde(default)]
+  pub min_size: Option<ByteSize>,
+
+  /// Excludes files last modified before this: a duration before now (eg. "7d", "12h") or a date
+  /// (eg. "2024-01-31", "2024-01-31 12:00").
+  /// In JSON-config-mode, the latest of the CLI, global and per-operation values applies.
+  #[arg(
+    long = "newer-than",
+    alias = "nt"
+  )]
+  #[serde(default)]
+  pub newer_than: Option<TimeBound>,
+
+  /// Excludes files last modified after this: a duration before now (eg. "30d") or a date (eg. "2024-01-31").
+  /// In JSON-config-mode, the earliest of the CLI, global and per-operation values applies.
+  #[arg(
+    long = "older-than",
+    alias = "ot"
+  )]
+  #[serde(default)]
+  pub older_than: Option<TimeBound>,
+
+  /// Only includes entries of these types; excludes all others. Symlinks are not followed.
+  /// Accepts one or multiple values.
+  /// In JSON-config-mode, this will be merged with the global file types defined in the JSON.
+  #[arg(
+    long = "file-types",
+    alias = "ft",
+    num_args = 0..,
+    value_enum
+  )]
+  #[serde(default)]
+  pub file_types: Vec<FileType>,
+
+  /// Matches dir, file and pattern rules case-insensitively (eg. "Thumbs.db" also matches "THUMBS.DB").
+  /// Regexes can use "(?i)" instead.
+  /// In JSON-config-mode, this is set for all operations if set globally.
+  #[arg(
+    long = "case-insensitive-rules",
+    alias = "ci",
+    action // = false if not given, true if present
+  )]
+  #[serde(default)] // defaults to false
+  pub case_insensitive_rules: bool,
+
+  /// Also applies ".gitignore" files found in the source, like ".baccyignore" files.
+  /// In JSON-config-mode, this is set for all operations if set globally.
+  #[arg(
+    long = "gitignore",
+    alias = "gi",
+    action // = false if not given, true if present
+  )]
+  #[serde(default)] // defaults to false
+  pub gitignore: bool,
+}
+
+impl RuleArgs {
+  /// Merges other rules into these, keeping each list sorted and without duplicates.
+  pub fn merge(&mut self, other: &RuleArgs) {
+    // merge excludes
+    self.exclude_dirs = merge_sort_dedup(&self.exclude_dirs, &other.exclude_dirs);
+    self.exclude_files = merge_sort_dedup(&self.exclude_files, &other.exclude_files);
+    self.exclude_patterns = merge_sort_dedup(&self.exclude_patterns, &other.exclude_patterns);
+    self.exclude_regex = merge_sort_dedup(&self.exclude_regex, &other.exclude_regex);
+    // merge includes
+    self.include_dirs = merge_sort_dedup(&self.include_dirs, &other.include_dirs);
+    self.include_files = merge_sort_dedup(&self.include_files, &other.include_files);
+    self.include_patterns = merge_sort_dedup(&self.include_patterns, &other.include_patterns);
+    self.include_regex = merge_sort_dedup(&self.include_regex, &other.include_regex);
+    // merge force-includes
+    self.force_include_dirs = merge_sort_dedup(&self.force_include_dirs, &other.force_include_dirs);
+    self.force_include_files = merge_sort_dedup(&self.force_include_files, &other.force_include_files);
+    self.force_include_patterns = merge_sort_dedup(&self.force_include_patterns, &other.force_include_patterns);
+    self.force_include_regex = merge_sort_dedup(&self.force_include_regex, &other.force_include_regex);
+    // merge limits: the strictest applies
+    self.max_size = stricter(self.max_size.take(), other.max_size, |a, b| a.0 <= b.0);
+    self.min_size = stricter(self.min_size.take(), other.min_size, |a, b| a.0 >= b.0);
+    self.newer_than = stricter(self.newer_than.take(), other.newer_than.clone(), |a, b| a.time >= b.time);
+    self.older_than = stricter(self.older_than.take(), other.older_than.clone(), |a, b| a.time <= b.time);
+    self.file_types.extend(&other.file_types);
+    self.file_types.sort();
+    self.file_types.dedup();
+    self.case_insensitive_rules |= other.case_insensitive_rules;
+    self.gitignore |= other.gitignore;
+  }
+}
+
+/// Returns the stricter of two optional limits; `is_stricter(a, b)` is true if `a` is at least as strict as `b`.
+fn stricter<T>(a: Option<T>, b: Option<T>, is_stricter: impl Fn(&T, &T) -> bool) -> Option<T> {
+  match (a, b) {
+    (Some(a), Some(b)) => Some(if is_stricter(&a, &b) {a} else {b}),
+    (a, b) => a.or(b),
+  }
+}
+
+/// Merges two lists of rules, sorted and without duplicates.
+fn merge_sort_dedup(a: &[String], b: &[String]) -> Vec<String> {
+  let mut out = a.iter().chain(b.iter()).cloned().collect::<Vec<_>>();
+  out.sort();
+  out.dedup();
+  out
+}
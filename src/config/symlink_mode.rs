@@ -0,0 +1,24 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Defines how symlinks in the source are synced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkMode {
+  /// Recreate symlinks in the target, pointing to the same path.
+  #[default]
+  Preserve,
+  /// Copy what symlinks point to; linked directories are synced like regular ones.
+  Follow,
+  /// Ignore symlinks.
+  Skip,
+  /// Like follow, but skip symlinks pointing outside of the source.
+  FollowSafe,
+}
+
+impl SymlinkMode {
+  /// Returns true if the source walk follows symlinks.
+  pub fn follows(self) -> bool {
+    matches!(self, SymlinkMode::Follow | SymlinkMode::FollowSafe)
+  }
+}
@@ -0,0 +1,14 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Defines how the target is kept in sync with the source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncMode {
+  /// Target directly contains a copy of the source.
+  #[default]
+  Mirror,
+  /// Each run creates a new snapshot directory in the target;
+  /// unchanged files are hard-linked to the previous snapshot.
+  Snapshot,
+}
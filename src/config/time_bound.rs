@@ -0,0 +1,45 @@
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone};
+use serde::{Deserialize, Deserializer};
+use std::{fmt, str::FromStr, time::SystemTime};
+
+/// A point in time for age rules; parsed from a duration before now (eg. `7d`, `12h`, `1week 2days`)
+/// or a local date, optionally with time (`2024-01-31`, `2024-01-31 12:00`, `2024-01-31T12:00:00`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeBound {
+  /// As given, for printing.
+  pub text: String,
+  pub time: SystemTime,
+}
+
+impl FromStr for TimeBound {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let trimmed = s.trim();
+    let time = if let Ok(duration) = humantime::parse_duration(trimmed) {
+      SystemTime::now().checked_sub(duration).ok_or_else(|| format!("Duration '{}' is too long.", s))?
+    } else {
+      let datetime = NaiveDate::parse_from_str(trimmed, "%Y-%m-%d").map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+        .or_else(|_| NaiveDateTime::parse_from_str(trimmed, "%Y-%m-%d %H:%M"))
+        .or_else(|_| NaiveDateTime::parse_from_str(trimmed, "%Y-%m-%d %H:%M:%S"))
+        .or_else(|_| NaiveDateTime::parse_from_str(trimmed, "%Y-%m-%dT%H:%M:%S"))
+        .map_err(|_| format!("Invalid duration or date '{}'; use eg. '7d' or '2024-01-31'.", s))?;
+      Local.from_local_datetime(&datetime).earliest()
+        .ok_or_else(|| format!("Date '{}' doesn't exist in the local time zone.", s))?
+        .into()
+    };
+    Ok(TimeBound { text: trimmed.to_string(), time })
+  }
+}
+
+impl fmt::Display for TimeBound {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.text)
+  }
+}
+
+impl<'de> Deserialize<'de> for TimeBound {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+  }
+}
@@ -0,0 +1,317 @@
+use std::{collections::{HashMap, HashSet}, fs::{self, File, OpenOptions}, io::{self, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}};
+
+use crate::index::META_DIR;
+
+/// Block sizes grow with the square root of the file size (like rsync), within these bounds.
+const BLOCK_SIZE_MIN: usize = 4 * 1024;
+const BLOCK_SIZE_MAX: usize = 1024 * 1024;
+/// Amount of source data read at once.
+const READ_CHUNK: usize = 8 * 1024 * 1024;
+/// Directory in the target's meta directory, holding a marker for every update in progress.
+const MARKER_DIR: &str = "delta";
+
+fn marker_path(target: &Path, relative: &str) -> PathBuf {
+  target.join(META_DIR).join(MARKER_DIR).join(blake3::hash(relative.as_bytes()).to_hex().as_str())
+}
+
+/// Marks a file of the target (by its path relative to it) as being updated in place, until `finish` is called.
+/// An interrupted update leaves a partially updated file, with a new mtime and often its old size, which
+/// comparing metadata can't tell apart from an up to date one: the marker makes the next run update it again.
+pub fn start(target: &Path, relative: &str) -> io::Result<()> {
+  let marker = marker_path(target, relative);
+  fs::create_dir_all(marker.parent().unwrap())?;
+  let mut file = File::create(marker)?;
+  file.write_all(relative.as_bytes())?;
+  file.sync_all()
+}
+
+/// Removes the marker of a file after it was updated (or replaced) completely.
+pub fn finish(target: &Path, relative: &str) {
+  let _ = fs::remove_file(marker_path(target, relative));
+}
+
+/// Returns the files of the target (relative to it) whose update was interrupted.
+pub fn interrupted(target: &Path) -> HashSet<String> {
+  let Ok(entries) = fs::read_dir(target.join(META_DIR).join(MARKER_DIR)) else { return HashSet::new() };
+  entries
+    .filter_map(|entry| fs::read_to_string(entry.ok()?.path()).ok())
+    .collect()
+}
+
+/// Weak checksum over a window of bytes (as used by rsync), cheaply updated when the window moves by one byte.
+struct Rolling {
+  a: u32,
+  b: u32,
+  len: u32,
+}
+
+impl Rolling {
+  fn new(window: &[u8]) -> Self {
+    let len = window.len() as u32;
+    let (mut a, mut b) = (0u32, 0u32);
+    for (i, &byte) in window.iter().enumerate() {
+      a = a.wrapping_add(byte as u32);
+      b = b.wrapping_add((len - i as u32).wrapping_mul(byte as u32));
+    }
+    Self{a, b, len}
+  }
+
+  fn digest(&self) -> u32 {
+    (self.a & 0xffff) | (self.b << 16)
+  }
+
+  /// Moves the window by one byte: `out` leaves it at the front, `next` enters it at the back.
+  fn roll(&mut self, out: u8, next: u8) {
+    self.a = self.a.wrapping_sub(out as u32).wrapping_add(next as u32);
+    self.b = self.b.wrapping_sub(self.len.wrapping_mul(out as u32)).wrapping_add(self.a);
+  }
+}
+
+/// Checksums of all blocks of the existing destination file: weak checksum -> (offset, strong hash).
+struct Signature {
+  blocks: HashMap<u32, Vec<(u64, blake3::Hash)>>,
+}
+
+impl Signature {
+  fn read(path: &Path, block_size: usize) -> io::Result<Self> {
+    let mut reader = File::open(path)?;
+    let mut blocks: HashMap<u32, Vec<(u64, blake3::Hash)>> = HashMap::new();
+    let mut buffer = vec![0u8; block_size];
+    let mut offset: u64 = 0;
+    loop {
+      let num_bytes = read_full(&mut reader, &mut buffer)?;
+      if num_bytes == 0 {break;}
+      let block = &buffer[..num_bytes];
+      blocks.entry(Rolling::new(block).digest()).or_default().push((offset, blake3::hash(block)));
+      offset += num_bytes as u64;
+      if num_bytes < block_size {break;}
+    }
+    Ok(Self{blocks})
+  }
+
+  /// Returns whether the window matches any block and whether it matches the block at the same offset.
+  fn find(&self, weak: u32, window: &[u8], offset: u64) -> (bool, bool) {
+    let Some(candidates) = self.blocks.get(&weak) else { return (false, false) };
+    let strong = blake3::hash(window);
+    let mut found = false;
+    for (block_offset, hash) in candidates {
+      if *hash == strong {
+        if *block_offset == offset { return (true, true); }
+        found = true;
+      }
+    }
+    (found, false)
+  }
+}
+
+/// Fills the buffer as far as possible; returns the number of bytes read (less than the buffer only at EOF).
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+  let mut filled = 0;
+  while filled < buffer.len() {
+    match reader.read(&mut buffer[filled..]) {
+      Ok(0) => break,
+      Ok(num_bytes) => filled += num_bytes,
+      Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+      Err(err) => return Err(err),
+    }
+  }
+  Ok(filled)
+}
+
+fn write_at(writer: &mut File, offset: u64, data: &[u8]) -> io::Result<u64> {
+  if data.is_empty() { return Ok(0); }
+  writer.seek(SeekFrom::Start(offset))?;
+  writer.write_all(data)?;
+  Ok(data.len() as u64)
+}
+
+/// Updates `dst` in place to equal `src`, rsync-style: checksums of the blocks of `dst` are matched against every
+/// window of `src` using a rolling checksum; only data not found at the same offset in `dst` is written.
+/// Calls `on_progress` with the number of source bytes processed; feeds the source's content into `hasher`, if given.
+/// Returns the number of bytes written. Not atomic: see `start` for marking the update as in progress.
+pub fn update_in_place(
+  src: &Path,
+  dst: &Path,
+  mut hasher: Option<&mut blake3::Hasher>,
+  mut on_progress: impl FnMut(u64)
+) -> io::Result<u64> {
+  let src_len = fs::metadata(src)?.len();
+  let dst_len = fs::metadata(dst)?.len();
+  let block_size = ((dst_len as f64).sqrt() as usize).next_power_of_two().clamp(BLOCK_SIZE_MIN, BLOCK_SIZE_MAX);
+  let signature = Signature::read(dst, block_size)?;
+
+  let mut reader = File::open(src)?;
+  let mut writer = OpenOptions::new().write(true).open(dst)?;
+  let mut written: u64 = 0;
+
+  // `buffer` holds source data starting at `buffer_offset`; the current window starts at `pos`,
+  // changed data not written yet starts at `pending`.
+  let mut buffer: Vec<u8> = Vec::with_capacity(READ_CHUNK + block_size);
+  let mut buffer_offset: u64 = 0;
+  let mut pos: usize = 0;
+  let mut pending: usize = 0;
+  let mut eof = false;
+  let mut rolling: Option<Rolling> = None;
+
+  loop {
+    if buffer.len() - pos < block_size && !eof {
+      // write pending changes and drop processed data, then read more
+      written += write_at(&mut writer, buffer_offset + pending as u64, &buffer[pending..pos])?;
+      buffer.drain(..pos);
+      buffer_offset += pos as u64;
+      pos = 0;
+      pending = 0;
+      let filled = buffer.len();
+      buffer.resize(filled + READ_CHUNK, 0);
+      let num_bytes = read_full(&mut reader, &mut buffer[filled..])?;
+      buffer.truncate(filled + num_bytes);
+      if let Some(hasher) = hasher.as_deref_mut() {
+        hasher.update(&buffer[filled..]);
+      }
+      eof = num_bytes < READ_CHUNK;
+      on_progress(buffer_offset);
+    }
+    if buffer.len() - pos < block_size {break;}
+
+    let window = &buffer[pos..pos + block_size];
+    let weak = rolling.get_or_insert_with(|| Rolling::new(window)).digest();
+    let offset = buffer_offset + pos as u64;
+    match signature.find(weak, window, offset) {
+      (true, unchanged) => {
+        if unchanged {
+          // block is already in place: write what changed before it
+          written += write_at(&mut writer, buffer_offset + pending as u64, &buffer[pending..pos])?;
+          pending = pos + block_size;
+        }
+        // moved blocks stay pending: the source is local, so their data is written from there
+        pos += block_size;
+        rolling = None;
+      }
+      (false, _) => {
+        match (rolling.as_mut(), buffer.get(pos + block_size)) {
+          (Some(rolling), Some(&next)) => rolling.roll(buffer[pos], next),
+          _ => rolling = None,
+        }
+        pos += 1;
+      }
+    }
+  }
+
+  // last, shorter block: unchanged only if the target ends with the same data at the same offset
+  let tail = &buffer[pos..];
+  let offset = buffer_offset + pos as u64;
+  let tail_unchanged = !tail.is_empty()
+    && offset + tail.len() as u64 == dst_len
+    && signature.find(Rolling::new(tail).digest(), tail, offset).1;
+  let end = if tail_unchanged {pos} else {buffer.len()};
+  written += write_at(&mut writer, buffer_offset + pending as u64, &buffer[pending..end])?;
+
+  writer.set_len(src_len)?;
+  writer.sync_all()?;
+  on_progress(src_len);
+  Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::test_dir::TestDir;
+
+  /// Deterministic, incompressible test data.
+  fn data(seed: &str, len: usize) -> Vec<u8> {
+    let mut data = vec![0u8; len];
+    blake3::Hasher::new().update(seed.as_bytes()).finalize_xof().fill(&mut data);
+    data
+  }
+
+  /// Writes both files, updates the destination and returns the bytes written; checks the result and the hash.
+  fn update(name: &str, src: &[u8], dst: &[u8]) -> u64 {
+    let dir = TestDir::new(&format!("delta-{}", name));
+    let (src_path, dst_path) = (dir.join("src"), dir.join("dst"));
+    fs::write(&src_path, src).unwrap();
+    fs::write(&dst_path, dst).unwrap();
+
+    let mut hasher = blake3::Hasher::new();
+    let written = update_in_place(&src_path, &dst_path, Some(&mut hasher), |_| {}).unwrap();
+    let result = fs::read(&dst_path).unwrap();
+
+    assert!(result == src, "destination differs from source");
+    assert_eq!(hasher.finalize(), blake3::hash(src));
+    written
+  }
+
+  #[test]
+  fn rolls_like_a_fresh_checksum() {
+    let data = data("rolling", 4096 + 100);
+    let mut rolling = Rolling::new(&data[..4096]);
+    for pos in 0..100 {
+      rolling.roll(data[pos], data[pos + 4096]);
+      assert_eq!(rolling.digest(), Rolling::new(&data[pos + 1..pos + 1 + 4096]).digest());
+    }
+  }
+
+  #[test]
+  fn writes_nothing_for_identical_files() {
+    let file = data("identical", 300_000);
+    assert_eq!(update("identical", &file, &file), 0);
+  }
+
+  #[test]
+  fn writes_only_changed_blocks() {
+    let dst = data("changed", 300_000);
+    let mut src = dst.clone();
+    src[150_000..150_010].copy_from_slice(&[0; 10]);
+    let written = update("changed", &src, &dst);
+    assert!(written > 0 && written <= 2 * BLOCK_SIZE_MIN as u64, "wrote {written} bytes");
+  }
+
+  #[test]
+  fn updates_growing_files() {
+    let dst = data("growing", 300_000);
+    let src = [dst.as_slice(), &data("appended", 10_000)].concat();
+    let written = update("growing", &src, &dst);
+    assert!((10_000..20_000).contains(&written), "wrote {written} bytes");
+  }
+
+  #[test]
+  fn updates_shrinking_files() {
+    let dst = data("shrinking", 300_000);
+    let src = dst[..290_000].to_vec();
+    let written = update("shrinking", &src, &dst);
+    assert!(written < 10_000, "wrote {written} bytes");
+    assert_eq!(update("emptied", &[], &dst), 0);
+  }
+
+  #[test]
+  fn rewrites_shifted_data() {
+    // an insertion at the front moves every block, which is then written from the source
+    let dst = data("shifted", 100_000);
+    let src = [b"inserted".as_slice(), &dst].concat();
+    update("shifted", &src, &dst);
+  }
+
+  #[test]
+  fn marks_interrupted_updates() {
+    let target = TestDir::new("delta-marker");
+    assert!(interrupted(&target).is_empty());
+
+    let (src, dst) = (data("marker", 100_000), data("marker-old", 100_000));
+    fs::write(target.join("src"), &src).unwrap();
+    fs::write(target.join("file"), &dst).unwrap();
+    start(&target, "dir/file").unwrap();
+    start(&target, "file").unwrap();
+    // interrupted after writing some of the changes: same size, new mtime, but still different
+    let mut file = OpenOptions::new().write(true).open(target.join("file")).unwrap();
+    file.write_all(&src[..50_000]).unwrap();
+    drop(file);
+    assert_eq!(interrupted(&target), HashSet::from([String::from("dir/file"), String::from("file")]));
+
+    // the next run updates it again
+    update_in_place(&target.join("src"), &target.join("file"), None, |_| {}).unwrap();
+    finish(&target, "file");
+    assert!(fs::read(target.join("file")).unwrap() == src);
+    assert_eq!(interrupted(&target), HashSet::from([String::from("dir/file")]));
+    finish(&target, "dir/file");
+    assert!(interrupted(&target).is_empty());
+  }
+}
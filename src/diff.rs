@@ -0,0 +1,80 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+
+use colored::{ColoredString, Colorize};
+use indicatif::ProgressBar;
+
+use crate::{
+  progress_helpers::setup_spinner,
+  tree_diff::{diff_trees, Change, Difference, TreeDiff, TreePair},
+  util::bytes_to_string::bytes_to_string
+};
+
+/// Runs the "diff" subcommand: prints what a sync would change, without modifying anything.
+/// Either compares a single source/target pair, or all operations (or the named one) of a JSON config.
+pub fn diff_command(source: &Path, target: Option<PathBuf>, operation: Option<String>) {
+  let (pairs, errors) = TreePair::resolve(source, target, operation.as_deref());
+
+  for pair in &pairs {
+    println!();
+    println!("{}", format!("Diff:   {} → {}", pair.source.to_str().unwrap().cyan(), pair.target.to_str().unwrap().cyan()).bold());
+    let mut progress = ProgressBar::new_spinner();
+    setup_spinner(&mut progress, "Comparing files...");
+    let diff = diff_trees(pair, pair.compare);
+    progress.finish_and_clear();
+    print_diff(pair.name.as_deref(), &diff);
+  }
+
+  for err in &errors {
+    println!("{}", err.bright_red());
+  }
+  println!();
+}
+
+fn label(change: Change) -> ColoredString {
+  match change {
+    Change::New => "NEW".green(),
+    Change::Modified => "MODIFIED".yellow(),
+    Change::Deleted => "DELETED".red(),
+    Change::Excluded => "EXCLUDED".dimmed(),
+  }.bold()
+}
+
+/// Prints differences grouped by their directory, with byte totals per directory and change.
+fn print_diff(name: Option<&str>, diff: &TreeDiff) {
+  let mut groups: BTreeMap<&Path, Vec<&Difference>> = BTreeMap::new();
+  for difference in &diff.differences {
+    let dir = Path::new(&difference.path).parent().unwrap_or(Path::new(""));
+    groups.entry(dir).or_default().push(difference);
+  }
+
+  for (dir, mut differences) in groups {
+    differences.sort_by(|a, b| a.path.cmp(&b.path));
+    let bytes: u64 = differences.iter().map(|d| d.bytes).sum();
+    let dir = if dir.as_os_str().is_empty() {String::from(".")} else {dir.display().to_string()};
+    println!("{} {}", format!("{}/", dir).bold(), format!("({} files, {})", differences.len(), bytes_to_string(bytes)).dimmed());
+    for difference in differences {
+      println!("{:>10}: {} {}",
+        label(difference.change),
+        difference.path,
+        format!("({})", bytes_to_string(difference.bytes)).dimmed()
+      );
+    }
+  }
+  for err in &diff.errors {
+    println!("{:>10}: {}", "ERROR".bright_red().bold(), err);
+  }
+
+  let total = |change: Change, name: &str| {
+    let bytes = diff.differences.iter().filter(|d| d.change == change).map(|d| d.bytes).sum();
+    format!("{} {} ({})", diff.count(change).to_string().cyan(), name, bytes_to_string(bytes))
+  };
+  println!("{} {}Compared {} files: {}, {}, {}, {}.",
+    "▪▪▪".blue(),
+    name.map(|n| format!("{}: ", n)).unwrap_or_default(),
+    diff.compared.to_string().cyan(),
+    total(Change::New, "new"),
+    total(Change::Modified, "modified"),
+    total(Change::Deleted, "deleted"),
+    total(Change::Excluded, "excluded")
+  );
+}
@@ -0,0 +1,129 @@
+use std::{fs, mem, path::Path};
+
+use colored::Colorize;
+
+use crate::{
+  config::{json::JSONConfig, rule_args::RuleArgs},
+  filter::{describe_limits, Decision, Family, Filter, Rule, RuleKind}
+};
+
+/// Returns the rules of a family and kind.
+fn rule_list(args: &RuleArgs, family: Family, kind: RuleKind) -> &[String] {
+  match (family, kind) {
+    (Family::Exclude, RuleKind::Dir) => &args.exclude_dirs,
+    (Family::Exclude, RuleKind::File) => &args.exclude_files,
+    (Family::Exclude, RuleKind::Pattern) => &args.exclude_patterns,
+    (Family::Exclude, RuleKind::Regex) => &args.exclude_regex,
+    (Family::Include, RuleKind::Dir) => &args.include_dirs,
+    (Family::Include, RuleKind::File) => &args.include_files,
+    (Family::Include, RuleKind::Pattern) => &args.include_patterns,
+    (Family::Include, RuleKind::Regex) => &args.include_regex,
+    (Family::ForceInclude, RuleKind::Dir) => &args.force_include_dirs,
+    (Family::ForceInclude, RuleKind::File) => &args.force_include_files,
+    (Family::ForceInclude, RuleKind::Pattern) => &args.force_include_patterns,
+    (Family::ForceInclude, RuleKind::Regex) => &args.force_include_regex,
+    (_, RuleKind::Limit) => &[],
+  }
+}
+
+fn kind_name(kind: RuleKind) -> &'static str {
+  match kind {
+    RuleKind::Dir => "dir",
+    RuleKind::File => "file",
+    RuleKind::Pattern => "pattern",
+    RuleKind::Regex => "regex",
+    RuleKind::Limit => "limit",
+  }
+}
+
+/// Runs the "explain" subcommand: evaluates the rules of all operations (or the named one) in a JSON config
+/// for a path and prints every matching rule, where it's defined (CLI, global or operation), and the decision.
+/// The path is relative to the source, or a path inside the source.
+pub fn explain_command(config: &Path, path: &Path, operation: Option<String>, cli_rules: RuleArgs) {
+  let mut config = JSONConfig::read(config);
+  // keep global rules apart from the operations' own rules, to tell where matching rules are defined
+  let global_rules = mem::take(&mut config.rules);
+
+  for op in config.take_operations(operation.as_deref()) {
+    println!();
+    println!("{}", format!("Explain:  {}{}",
+      op.name.as_deref().map(|n| format!("{}: ", n)).unwrap_or_default(),
+      op.source.to_str().unwrap().cyan()
+    ).bold());
+    let relative_path = if path.is_absolute() {
+      match path.strip_prefix(&op.source) {
+        Ok(relative) => relative,
+        Err(_) => {
+          println!("{} Path is not in this operation's source.", "▪▪▪".blue());
+          continue;
+        }
+      }
+    } else {
+      path
+    };
+
+    let mut merged = op.rules.clone();
+    merged.merge(&global_rules);
+    merged.merge(&cli_rules);
+    let filter = match Filter::new(&merged) {
+      Ok(filter) => filter,
+      Err(err) => {
+        println!("{}", err.to_string().bright_red());
+        continue;
+      }
+    };
+
+    // where a rule is defined
+    let origins = |rule: &Rule| {
+      [("CLI", &cli_rules), ("global", &global_rules), ("operation", &op.rules)].into_iter()
+        .filter(|(_, args)| match rule.kind {
+          RuleKind::Limit => describe_limits(args).iter().any(|r| r == rule.rule),
+          _ => rule_list(args, rule.family, rule.kind).iter().any(|r| r == rule.rule),
+        })
+        .map(|(origin, _)| origin)
+        .collect::<Vec<_>>()
+        .join(", ")
+    };
+    let describe = |rule: &Rule| match rule.kind {
+      RuleKind::Limit => format!("{} {}", rule.rule, format!("({})", origins(rule)).dimmed()),
+      _ => format!("{} \"{}\" {}", kind_name(rule.kind), rule.rule, format!("({})", origins(rule)).dimmed()),
+    };
+
+    // ignore files take precedence over all rules
+    let is_dir = op.source.join(relative_path).is_dir();
+    let mut ignore_files = filter.ignore_files();
+    let ignored = ignore_files.check_path(&op.source, relative_path, is_dir);
+    for err in &ignore_files.errors {
+      println!("{:>10}: {}", "ERROR".bright_red().bold(), err);
+    }
+    if let Some(glob) = ignored {
+      let from = glob.from().map(|file| file.display().to_string()).unwrap_or_default();
+      println!("{:>10}: \"{}\" {}", "IGNORE".bold(), glob.original(), format!("({})", from).dimmed());
+      println!("{} {}: {} by \"{}\" in {}", "▪▪▪".blue(), relative_path.display(), "Ignored".red(), glob.original(), from);
+      continue;
+    }
+
+    for (label, set) in [("EXCLUDE", &filter.exclude), ("INCLUDE", &filter.include), ("FORCE", &filter.force_include)] {
+      for rule in set.matching(relative_path) {
+        println!("{:>10}: {}", label.bold(), describe(&rule));
+      }
+    }
+    // limits can only be checked for existing files
+    let metadata = fs::symlink_metadata(op.source.join(relative_path)).ok();
+    if let Some(metadata) = &metadata {
+      for rule in filter.violated_limits(metadata) {
+        println!("{:>10}: {}", "LIMIT".bold(), describe(&rule));
+      }
+    }
+
+    let decision = match filter.decide(relative_path, metadata.as_ref()) {
+      Decision::Included(None) => "Included, no rules restrict it.".green().to_string(),
+      Decision::Included(Some(rule)) => format!("{} by {}", "Included".green(), describe(&rule)),
+      Decision::Excluded(rule) => format!("{} by {}", "Excluded".red(), describe(&rule)),
+      Decision::NotIncluded(kind) => format!("{} No include {} rule matches.", "Not included.".red(), kind_name(kind)),
+      Decision::ForceIncluded(rule) => format!("{} by {}", "Force-included".green(), describe(&rule)),
+    };
+    println!("{} {}: {}", "▪▪▪".blue(), relative_path.display(), decision);
+  }
+  println!();
+}
@@ -0,0 +1,636 @@
+use std::{fmt, fs::Metadata, path::{Component, Path}, time::SystemTime};
+
+use glob::{MatchOptions, Pattern, PatternError};
+use regex::Regex;
+
+use crate::{
+  config::{file_type::FileType, rule_args::RuleArgs},
+  ignore_files::IgnoreFiles,
+  util::bytes_to_string::bytes_to_string
+};
+
+/// Rule family; exclusions are checked first, then inclusions, then force-inclusions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+  Exclude,
+  Include,
+  ForceInclude,
+}
+
+/// What a rule matches against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+  /// Exact name of any parent directory.
+  Dir,
+  /// Exact file name.
+  File,
+  /// Pattern matching the path relative to the source.
+  Pattern,
+  /// Regular expression matching the path relative to the source.
+  Regex,
+  /// Size, age or type of the file; only used for exclusions.
+  Limit,
+}
+
+impl RuleKind {
+  /// Kinds of rules matching names.
+  pub const BY_NAME: [RuleKind; 4] = [RuleKind::Dir, RuleKind::File, RuleKind::Pattern, RuleKind::Regex];
+}
+
+/// A rule that matched a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule<'a> {
+  pub family: Family,
+  pub kind: RuleKind,
+  /// The rule as given (directory name, file name, pattern or regex), or a description of the limit.
+  pub rule: &'a str,
+}
+
+/// Whether a file is considered for copying, and which rule decided it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision<'a> {
+  /// Not excluded, and passes the include rules. Holds the first matching include rule, if there are any.
+  Included(Option<Rule<'a>>),
+  /// Matches an exclude rule or violates a limit (the first one), and no force-include rule.
+  Excluded(Rule<'a>),
+  /// There are include rules of this kind, but none of them matches; and no force-include rule matches.
+  NotIncluded(RuleKind),
+  /// Excluded or not included, but matches a force-include rule (the first one).
+  ForceIncluded(Rule<'a>),
+}
+
+impl Decision<'_> {
+  pub fn is_included(&self) -> bool {
+    matches!(self, Decision::Included(_) | Decision::ForceIncluded(_))
+  }
+}
+
+/// An invalid pattern or regex in the rules.
+#[derive(Debug)]
+pub enum FilterError {
+  Pattern { pattern: String, error: PatternError },
+  Regex { regex: String, error: regex::Error },
+}
+
+impl fmt::Display for FilterError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      FilterError::Pattern { pattern, error } =>
+        write!(f, "Invalid pattern \"{}\": {} (at position {})", pattern, error.msg, error.pos),
+      // syntax errors span multiple lines, pointing at the error in the regex
+      FilterError::Regex { regex, error } => write!(f, "Invalid regex \"{}\": {}", regex, error),
+    }
+  }
+}
+
+impl std::error::Error for FilterError {}
+
+/// Dir, file, pattern and regex rules of one family, with patterns and regexes parsed.
+#[derive(Debug)]
+pub struct RuleSet {
+  family: Family,
+  dirs: Vec<String>,
+  files: Vec<String>,
+  patterns: Vec<Pattern>,
+  regexes: Vec<Regex>,
+  /// Whether dir, file and pattern rules ignore case.
+  case_insensitive: bool,
+}
+
+fn parse_patterns(patterns: &[String]) -> Result<Vec<Pattern>, FilterError> {
+  patterns
+    .iter()
+    .map(|p| Pattern::new(p).map_err(|error| FilterError::Pattern{pattern: p.clone(), error}))
+    .collect()
+}
+
+fn parse_regexes(regexes: &[String]) -> Result<Vec<Regex>, FilterError> {
+  regexes
+    .iter()
+    .map(|r| Regex::new(r).map_err(|error| FilterError::Regex{regex: r.clone(), error}))
+    .collect()
+}
+
+/// Compares a name rule to a file or directory name.
+fn names_match(rule: &str, name: &str, case_insensitive: bool) -> bool {
+  if case_insensitive {rule.to_lowercase() == name.to_lowercase()} else {rule == name}
+}
+
+/// Joins the components of a relative path with '/', the separator regexes are written for.
+fn slash_path(relative_path: &Path) -> String {
+  relative_path.components()
+    .map(|c| c.as_os_str().to_string_lossy())
+    .collect::<Vec<_>>()
+    .join("/")
+}
+
+impl RuleSet {
+  fn new(
+    family: Family, dirs: &[String], files: &[String], patterns: &[String], regexes: &[String], case_insensitive: bool
+  ) -> Result<Self, FilterError> {
+    Ok(Self {
+      family,
+      dirs: dirs.to_vec(),
+      files: files.to_vec(),
+      patterns: parse_patterns(patterns)?,
+      regexes: parse_regexes(regexes)?,
+      case_insensitive,
+    })
+  }
+
+  fn empty(family: Family) -> Self {
+    Self { family, dirs: Vec::new(), files: Vec::new(), patterns: Vec::new(), regexes: Vec::new(), case_insensitive: false }
+  }
+
+  fn match_options(&self) -> MatchOptions {
+    MatchOptions { case_sensitive: !self.case_insensitive, ..MatchOptions::new() }
+  }
+
+  /// Returns true if there are no rules of a kind.
+  pub fn is_empty(&self, kind: RuleKind) -> bool {
+    match kind {
+      RuleKind::Dir => self.dirs.is_empty(),
+      RuleKind::File => self.files.is_empty(),
+      RuleKind::Pattern => self.patterns.is_empty(),
+      RuleKind::Regex => self.regexes.is_empty(),
+      RuleKind::Limit => true,
+    }
+  }
+
+  /// Returns the rules matching a file (given by its path relative to the source), lazily.
+  pub fn matching<'a>(&'a self, relative_path: &'a Path) -> impl Iterator<Item = Rule<'a>> + 'a {
+    let family = self.family;
+    let case_insensitive = self.case_insensitive;
+    let options = self.match_options();
+    let relative_path_parentdirs = relative_path.parent().unwrap_or_else(|| Path::new(""));
+    let file_name = relative_path.file_name().and_then(|name| name.to_str());
+
+    // dir name - exact
+    let dirs = self.dirs.iter()
+      .filter(move |dir| relative_path_parentdirs.components().any(|c| match c {
+        Component::Normal(os) => names_match(dir, &os.to_string_lossy(), case_insensitive),
+        _ => false
+      }))
+      .map(move |dir| Rule{family, kind: RuleKind::Dir, rule: dir});
+    // file name - exact
+    let files = self.files.iter()
+      .filter(move |file| file_name.is_some_and(|name| names_match(file, name, case_insensitive)))
+      .map(move |file| Rule{family, kind: RuleKind::File, rule: file});
+    // pattern match
+    let patterns = self.patterns.iter()
+      .filter(move |pattern| pattern.matches_path_with(relative_path, options))
+      .map(move |pattern| Rule{family, kind: RuleKind::Pattern, rule: pattern.as_str()});
+    // regex match; the path is only joined if there are regexes
+    let slash_path = if self.regexes.is_empty() {String::new()} else {slash_path(relative_path)};
+    let regexes = self.regexes.iter()
+      .filter(move |regex| regex.is_match(&slash_path))
+      .map(move |regex| Rule{family, kind: RuleKind::Regex, rule: regex.as_str()});
+
+    dirs.chain(files).chain(patterns).chain(regexes)
+  }
+
+  /// Returns true if the rules match all paths below a directory (given by its path relative to the source):
+  /// a dir rule matches the directory or one of its parents, or a pattern ends in "/**" and matches it otherwise.
+  fn matches_subtree(&self, relative_dir: &Path) -> bool {
+    relative_dir.components().any(|c| match c {
+      Component::Normal(os) => self.dirs.iter().any(|dir| names_match(dir, &os.to_string_lossy(), self.case_insensitive)),
+      _ => false
+    })
+    || self.patterns.iter().any(|pattern| pattern.as_str().strip_suffix("/**")
+      .and_then(|prefix| Pattern::new(prefix).ok())
+      .is_some_and(|prefix| prefix.matches_path_with(relative_dir, self.match_options())))
+  }
+
+  /// Returns true if any rule could match a path below a directory (given by its path relative to the source).
+  /// Errs on the side of caution: dir, file and regex rules could match any path, patterns could match unless
+  /// their literal leading directories differ from the directory's path.
+  fn could_match_below(&self, relative_dir: &Path) -> bool {
+    let fold = |s: &str| if self.case_insensitive {s.to_lowercase()} else {s.to_string()};
+    let mut dir = fold(&slash_path(relative_dir));
+    dir.push('/');
+    !self.dirs.is_empty() || !self.files.is_empty() || !self.regexes.is_empty() || self.patterns.iter().any(|pattern| {
+      let prefix = fold(literal_dir_prefix(pattern.as_str()));
+      dir.starts_with(&prefix) || prefix.starts_with(&dir)
+    })
+  }
+}
+
+/// Returns the leading directories of a pattern before its first wildcard, eg. "docs/" for "docs/**/*.md".
+fn literal_dir_prefix(pattern: &str) -> &str {
+  let literal = &pattern[..pattern.find(['*', '?', '[']).unwrap_or(pattern.len())];
+  &literal[..literal.rfind('/').map_or(0, |i| i + 1)]
+}
+
+/// A size, age or file type rule; files outside of it are excluded.
+#[derive(Debug)]
+enum LimitCheck {
+  MaxSize(u64),
+  MinSize(u64),
+  NewerThan(SystemTime),
+  OlderThan(SystemTime),
+  FileTypes(Vec<FileType>),
+}
+
+impl LimitCheck {
+  fn allows(&self, metadata: &Metadata) -> bool {
+    let modified = || metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    match self {
+      LimitCheck::MaxSize(max) => metadata.len() <= *max,
+      LimitCheck::MinSize(min) => metadata.len() >= *min,
+      LimitCheck::NewerThan(time) => modified() >= *time,
+      LimitCheck::OlderThan(time) => modified() <= *time,
+      LimitCheck::FileTypes(types) => FileType::of(metadata.file_type()).is_some_and(|t| types.contains(&t)),
+    }
+  }
+}
+
+#[derive(Debug)]
+struct Limit {
+  check: LimitCheck,
+  /// Printed in logs and decisions, eg. "max_size 1.00 MiB".
+  description: String,
+}
+
+fn limits(args: &RuleArgs) -> Vec<Limit> {
+  let mut limits = Vec::new();
+  let mut add = |check, description| limits.push(Limit{check, description});
+  if let Some(max) = args.max_size {
+    add(LimitCheck::MaxSize(max.0), format!("max_size {}", bytes_to_string(max.0)));
+  }
+  if let Some(min) = args.min_size {
+    add(LimitCheck::MinSize(min.0), format!("min_size {}", bytes_to_string(min.0)));
+  }
+  if let Some(newer) = &args.newer_than {
+    add(LimitCheck::NewerThan(newer.time), format!("newer_than {}", newer));
+  }
+  if let Some(older) = &args.older_than {
+    add(LimitCheck::OlderThan(older.time), format!("older_than {}", older));
+  }
+  if !args.file_types.is_empty() {
+    let names: Vec<&str> = args.file_types.iter().map(FileType::name).collect();
+    add(LimitCheck::FileTypes(args.file_types.clone()), format!("file_types {}", names.join(", ")));
+  }
+  limits
+}
+
+/// Describes the size, age and file type rules, as used in decisions.
+pub fn describe_limits(args: &RuleArgs) -> Vec<String> {
+  limits(args).into_iter().map(|limit| limit.description).collect()
+}
+
+/// Exclusion, inclusion and force-inclusion rules of an operation, deciding which files are copied.
+/// - Exclusions are combined with OR: a file matching any rule, or outside any limit, is excluded.
+/// - Inclusions are combined with OR within a kind (dirs, files, patterns, regexes) and AND across kinds;
+///   kinds without rules don't restrict. They're checked after exclusions.
+/// - Force-inclusions are combined with OR and override both.
+#[derive(Debug)]
+pub struct Filter {
+  pub exclude: RuleSet,
+  pub include: RuleSet,
+  pub force_include: RuleSet,
+  limits: Vec<Limit>,
+  /// Whether ".gitignore" files are applied, in addition to ".baccyignore" files.
+  pub gitignore: bool,
+}
+
+impl Filter {
+  /// Builds a filter from an operation's rules, validating all patterns and compiling all regexes.
+  pub fn new(args: &RuleArgs) -> Result<Self, FilterError> {
+    Ok(Self {
+      exclude: RuleSet::new(
+        Family::Exclude, &args.exclude_dirs, &args.exclude_files, &args.exclude_patterns, &args.exclude_regex,
+        args.case_insensitive_rules
+      )?,
+      include: RuleSet::new(
+        Family::Include, &args.include_dirs, &args.include_files, &args.include_patterns, &args.include_regex,
+        args.case_insensitive_rules
+      )?,
+      force_include: RuleSet::new(
+        Family::ForceInclude,
+        &args.force_include_dirs, &args.force_include_files, &args.force_include_patterns, &args.force_include_regex,
+        args.case_insensitive_rules
+      )?,
+      limits: limits(args),
+      gitignore: args.gitignore,
+    })
+  }
+
+  /// Returns the ignore files to apply while walking the source, in addition to the rules.
+  /// Ignore files take precedence; force-inclusions don't apply to ignored paths.
+  pub fn ignore_files(&self) -> IgnoreFiles {
+    IgnoreFiles::new(self.gitignore)
+  }
+
+  /// Returns the limits a file violates, lazily.
+  pub fn violated_limits<'a>(&'a self, metadata: &'a Metadata) -> impl Iterator<Item = Rule<'a>> + 'a {
+    self.limits.iter()
+      .filter(|limit| !limit.check.allows(metadata))
+      .map(|limit| Rule{family: Family::Exclude, kind: RuleKind::Limit, rule: &limit.description})
+  }
+
+  /// Decides whether a file (given by its path relative to the source) is considered for copying.
+  /// Limits are only checked if the file's metadata (not following symlinks) is given.
+  pub fn decide<'a>(&'a self, relative_path: &'a Path, metadata: Option<&'a Metadata>) -> Decision<'a> {
+    let violated = || metadata.and_then(|metadata| self.violated_limits(metadata).next());
+    let rejected = if let Some(rule) = self.exclude.matching(relative_path).next().or_else(violated) {
+      Decision::Excluded(rule)
+    } else {
+      let mut first_include = None;
+      let unmatched = RuleKind::BY_NAME.into_iter().find(|&kind| {
+        if self.include.is_empty(kind) {
+          return false;
+        }
+        match self.include.matching(relative_path).find(|rule| rule.kind == kind) {
+          Some(rule) => { first_include.get_or_insert(rule); false }
+          None => true,
+        }
+      });
+      match unmatched {
+        Some(kind) => Decision::NotIncluded(kind),
+        None => return Decision::Included(first_include),
+      }
+    };
+    match self.force_include.matching(relative_path).next() {
+      Some(rule) => Decision::ForceIncluded(rule),
+      None => rejected,
+    }
+  }
+
+  pub fn is_included(&self, relative_path: &Path, metadata: Option<&Metadata>) -> bool {
+    self.decide(relative_path, metadata).is_included()
+  }
+
+  /// Returns true if no file below a directory (given by its path relative to the source) can be included,
+  /// so the walk can skip it: it's excluded as a whole, and no force-include rule could match inside it.
+  pub fn excludes_dir(&self, relative_dir: &Path) -> bool {
+    self.exclude.matches_subtree(relative_dir) && !self.force_include.could_match_below(relative_dir)
+  }
+}
+
+impl Default for Filter {
+  /// A filter without rules, including everything.
+  fn default() -> Self {
+    Self {
+      exclude: RuleSet::empty(Family::Exclude),
+      include: RuleSet::empty(Family::Include),
+      force_include: RuleSet::empty(Family::ForceInclude),
+      limits: Vec::new(),
+      gitignore: false,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::byte_size::ByteSize;
+
+  fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+  }
+
+  fn filter(configure: impl FnOnce(&mut RuleArgs)) -> Filter {
+    let mut args = RuleArgs::default();
+    configure(&mut args);
+    Filter::new(&args).unwrap()
+  }
+
+  fn rule(family: Family, kind: RuleKind, rule: &str) -> Rule<'_> {
+    Rule{family, kind, rule}
+  }
+
+  #[test]
+  fn includes_everything_without_rules() {
+    let filter = Filter::default();
+    assert_eq!(filter.decide(Path::new("file.txt"), None), Decision::Included(None));
+    assert_eq!(filter.decide(Path::new("a/b/file.txt"), None), Decision::Included(None));
+  }
+
+  #[test]
+  fn excludes_dirs_recursively_by_exact_name() {
+    let filter = filter(|args| args.exclude_dirs = strings(&["target"]));
+    assert_eq!(
+      filter.decide(Path::new("target/debug/baccy"), None),
+      Decision::Excluded(rule(Family::Exclude, RuleKind::Dir, "target"))
+    );
+    assert!(!filter.is_included(Path::new("crate/target/file"), None));
+    // only parent directories count, not the file name itself or partial names
+    assert!(filter.is_included(Path::new("src/target"), None));
+    assert!(filter.is_included(Path::new("targets/file"), None));
+  }
+
+  #[test]
+  fn excludes_files_by_exact_name() {
+    let filter = filter(|args| args.exclude_files = strings(&["Thumbs.db"]));
+    assert_eq!(
+      filter.decide(Path::new("photos/Thumbs.db"), None),
+      Decision::Excluded(rule(Family::Exclude, RuleKind::File, "Thumbs.db"))
+    );
+    assert!(filter.is_included(Path::new("photos/Thumbs.db.bak"), None));
+  }
+
+  #[test]
+  fn excludes_patterns_relative_to_source() {
+    let filter = filter(|args| args.exclude_patterns = strings(&["src/**/*.tmp"]));
+    assert_eq!(
+      filter.decide(Path::new("src/a/b.tmp"), None),
+      Decision::Excluded(rule(Family::Exclude, RuleKind::Pattern, "src/**/*.tmp"))
+    );
+    assert!(filter.is_included(Path::new("other/src/a/b.tmp"), None));
+  }
+
+  #[test]
+  fn includes_need_any_rule_of_each_given_kind() {
+    let filter = filter(|args| {
+      args.include_dirs = strings(&["docs", "src"]);
+      args.include_files = strings(&["README.md"]);
+    });
+    assert_eq!(
+      filter.decide(Path::new("docs/README.md"), None),
+      Decision::Included(Some(rule(Family::Include, RuleKind::Dir, "docs")))
+    );
+    assert!(filter.is_included(Path::new("src/README.md"), None));
+    assert_eq!(filter.decide(Path::new("docs/other.md"), None), Decision::NotIncluded(RuleKind::File));
+    assert_eq!(filter.decide(Path::new("README.md"), None), Decision::NotIncluded(RuleKind::Dir));
+  }
+
+  #[test]
+  fn exclusions_take_precedence_over_inclusions() {
+    let filter = filter(|args| {
+      args.exclude_files = strings(&["secret.txt"]);
+      args.include_patterns = strings(&["*.txt"]);
+    });
+    assert_eq!(
+      filter.decide(Path::new("secret.txt"), None),
+      Decision::Excluded(rule(Family::Exclude, RuleKind::File, "secret.txt"))
+    );
+    assert!(filter.is_included(Path::new("public.txt"), None));
+  }
+
+  #[test]
+  fn force_inclusions_override_exclusions_and_inclusions() {
+    let filter = filter(|args| {
+      args.exclude_dirs = strings(&["build"]);
+      args.include_patterns = strings(&["**/*.rs"]);
+      args.force_include_files = strings(&["keep.bin"]);
+      args.force_include_dirs = strings(&["assets"]);
+    });
+    assert_eq!(
+      filter.decide(Path::new("build/keep.bin"), None),
+      Decision::ForceIncluded(rule(Family::ForceInclude, RuleKind::File, "keep.bin"))
+    );
+    assert_eq!(
+      filter.decide(Path::new("assets/logo.png"), None),
+      Decision::ForceIncluded(rule(Family::ForceInclude, RuleKind::Dir, "assets"))
+    );
+    // force-inclusions only show up if they change the decision
+    assert_eq!(
+      filter.decide(Path::new("assets/main.rs"), None),
+      Decision::Included(Some(rule(Family::Include, RuleKind::Pattern, "**/*.rs")))
+    );
+    assert!(!filter.is_included(Path::new("build/other.bin"), None));
+  }
+
+  #[test]
+  fn matching_lists_all_matching_rules() {
+    let filter = filter(|args| {
+      args.exclude_dirs = strings(&["a", "b", "c"]);
+      args.exclude_patterns = strings(&["a/**", "**/*.log"]);
+    });
+    let matching: Vec<&str> = filter.exclude.matching(Path::new("a/b/x.log")).map(|rule| rule.rule).collect();
+    assert_eq!(matching, ["a", "b", "a/**", "**/*.log"]);
+  }
+
+  #[test]
+  fn excludes_dirs_as_a_whole() {
+    let filter = filter(|args| {
+      args.exclude_dirs = strings(&["node_modules"]);
+      args.exclude_patterns = strings(&["**/build/**", "*.log"]);
+    });
+    assert!(filter.excludes_dir(Path::new("node_modules")));
+    assert!(filter.excludes_dir(Path::new("web/node_modules/react")));
+    assert!(filter.excludes_dir(Path::new("build")));
+    assert!(filter.excludes_dir(Path::new("app/build")));
+    assert!(!filter.excludes_dir(Path::new("app")));
+    assert!(!filter.excludes_dir(Path::new("app/builds")));
+    // only whole directories, even if every file in them happens to be excluded
+    assert!(!filter.excludes_dir(Path::new("logs")));
+  }
+
+  #[test]
+  fn keeps_dirs_force_inclusions_could_match_in() {
+    let by_pattern = filter(|args| {
+      args.exclude_dirs = strings(&["node_modules", "vendor"]);
+      args.force_include_patterns = strings(&["node_modules/keep/**", "docs/*.md"]);
+    });
+    assert!(!by_pattern.excludes_dir(Path::new("node_modules")));
+    assert!(!by_pattern.excludes_dir(Path::new("node_modules/keep/sub")));
+    assert!(by_pattern.excludes_dir(Path::new("node_modules/other")));
+    assert!(by_pattern.excludes_dir(Path::new("vendor")));
+
+    let by_file = filter(|args| {
+      args.exclude_dirs = strings(&["node_modules"]);
+      args.force_include_files = strings(&["package.json"]);
+    });
+    assert!(!by_file.excludes_dir(Path::new("node_modules")));
+
+    let by_any_pattern = filter(|args| {
+      args.exclude_dirs = strings(&["node_modules"]);
+      args.force_include_patterns = strings(&["**/*.md"]);
+    });
+    assert!(!by_any_pattern.excludes_dir(Path::new("node_modules")));
+  }
+
+  #[test]
+  fn excludes_files_outside_limits() {
+    let metadata = std::fs::metadata(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml")).unwrap();
+    let path = Path::new("Cargo.toml");
+    let decide = |configure: fn(&mut RuleArgs)| {
+      let filter = filter(configure);
+      filter.decide(path, Some(&metadata)).is_included()
+    };
+    assert!(decide(|args| args.max_size = Some(ByteSize(1024 * 1024))));
+    assert!(!decide(|args| args.max_size = Some(ByteSize(1))));
+    assert!(!decide(|args| args.min_size = Some(ByteSize(1024 * 1024))));
+    assert!(!decide(|args| args.older_than = Some("2000-01-01".parse().unwrap())));
+    assert!(decide(|args| args.newer_than = Some("2000-01-01".parse().unwrap())));
+    assert!(decide(|args| args.file_types = vec![FileType::File, FileType::Symlink]));
+    assert!(!decide(|args| args.file_types = vec![FileType::Symlink]));
+    // without metadata, limits are not checked
+    assert!(filter(|args| args.max_size = Some(ByteSize(1))).is_included(path, None));
+  }
+
+  #[test]
+  fn limits_are_excluded_like_rules() {
+    let metadata = std::fs::metadata(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml")).unwrap();
+    let filter = filter(|args| {
+      args.max_size = Some(ByteSize(1));
+      args.force_include_files = strings(&["Cargo.lock"]);
+    });
+    assert_eq!(
+      filter.decide(Path::new("Cargo.toml"), Some(&metadata)),
+      Decision::Excluded(rule(Family::Exclude, RuleKind::Limit, "max_size 0.00 kiB"))
+    );
+    assert!(filter.is_included(Path::new("Cargo.lock"), Some(&metadata)));
+  }
+
+  #[test]
+  fn matches_regexes_against_slash_separated_paths() {
+    let filter = filter(|args| {
+      args.exclude_regex = strings(&[r"(^|/)~\$[^/]*\.docx$", r"^build-\d+/"]);
+      args.include_regex = strings(&[r"\.(docx|txt)$"]);
+      args.force_include_regex = strings(&[r"^build-\d+/keep\.txt$"]);
+    });
+    assert_eq!(
+      filter.decide(Path::new("docs/~$report.docx"), None),
+      Decision::Excluded(rule(Family::Exclude, RuleKind::Regex, r"(^|/)~\$[^/]*\.docx$"))
+    );
+    assert!(filter.is_included(Path::new("docs/report.docx"), None));
+    assert!(!filter.is_included(Path::new("build-42/out.txt"), None));
+    assert!(filter.is_included(Path::new("old/build-42/out.txt"), None));
+    assert_eq!(filter.decide(Path::new("notes.md"), None), Decision::NotIncluded(RuleKind::Regex));
+    assert_eq!(
+      filter.decide(Path::new("build-42/keep.txt"), None),
+      Decision::ForceIncluded(rule(Family::ForceInclude, RuleKind::Regex, r"^build-\d+/keep\.txt$"))
+    );
+    // regexes never exclude directories as a whole; every file is checked
+    assert!(!filter.excludes_dir(Path::new("build-42")));
+  }
+
+  #[test]
+  fn matches_names_and_patterns_case_insensitively_if_enabled() {
+    let configure = |args: &mut RuleArgs| {
+      args.exclude_dirs = strings(&["Cache"]);
+      args.exclude_files = strings(&["Thumbs.db"]);
+      args.exclude_patterns = strings(&["**/*.TMP", "Build/**"]);
+    };
+    let sensitive = filter(configure);
+    assert!(sensitive.is_included(Path::new("photos/THUMBS.DB"), None));
+    assert!(sensitive.is_included(Path::new("cache/file"), None));
+    assert!(sensitive.is_included(Path::new("a/b.tmp"), None));
+
+    let insensitive = filter(|args| { configure(args); args.case_insensitive_rules = true; });
+    assert_eq!(
+      insensitive.decide(Path::new("photos/THUMBS.DB"), None),
+      Decision::Excluded(rule(Family::Exclude, RuleKind::File, "Thumbs.db"))
+    );
+    assert!(!insensitive.is_included(Path::new("photos/thumbs.db"), None));
+    assert!(!insensitive.is_included(Path::new("CACHE/file"), None));
+    assert!(!insensitive.is_included(Path::new("a/b.tmp"), None));
+    assert!(insensitive.excludes_dir(Path::new("cache")));
+    assert!(insensitive.excludes_dir(Path::new("build")));
+  }
+
+  #[test]
+  fn rejects_invalid_patterns() {
+    let args = RuleArgs { include_patterns: strings(&["ok/*", "src/[a-"]), ..Default::default() };
+    let err = Filter::new(&args).unwrap_err();
+    assert!(matches!(&err, FilterError::Pattern{pattern, ..} if pattern == "src/[a-"));
+    assert!(err.to_string().starts_with("Invalid pattern \"src/[a-\""));
+
+    let args = RuleArgs { exclude_regex: strings(&["ok", "(unclosed"]), ..Default::default() };
+    let err = Filter::new(&args).unwrap_err();
+    assert!(matches!(&err, FilterError::Regex{regex, ..} if regex == "(unclosed"));
+    assert!(err.to_string().starts_with("Invalid regex \"(unclosed\""));
+  }
+}
@@ -0,0 +1,183 @@
+use std::path::{Path, PathBuf};
+
+use ignore::{gitignore::{Gitignore, GitignoreBuilder, Glob}, Match};
+use walkdir::DirEntry;
+
+/// Rule files discovered in source directories; gitignore syntax, applied to the directory's subtree.
+pub const BACCYIGNORE: &str = ".baccyignore";
+/// Honored in addition to `BACCYIGNORE` with "--gitignore".
+pub const GITIGNORE: &str = ".gitignore";
+
+/// Rules of the ignore files found while walking a directory tree.
+/// Entries have to be checked in walk order (like `WalkDir` yields them): a directory's ignore files are
+/// loaded when the directory itself is checked, and apply to the entries below it. Rules of deeper ignore
+/// files take precedence, so they can re-include (`!pattern`) what a parent directory's file ignores.
+pub struct IgnoreFiles {
+  file_names: Vec<&'static str>,
+  /// Matchers of the directories above the current entry, with their depth; innermost last.
+  stack: Vec<(usize, Gitignore)>,
+  /// Problems reading or parsing ignore files; valid lines are still used.
+  pub errors: Vec<String>,
+}
+
+impl IgnoreFiles {
+  pub fn new(gitignore: bool) -> Self {
+    Self {
+      // later files take precedence
+      file_names: if gitignore {vec![GITIGNORE, BACCYIGNORE]} else {vec![BACCYIGNORE]},
+      stack: Vec::new(),
+      errors: Vec::new(),
+    }
+  }
+
+  /// Returns true if a walked entry is ignored; ignored directories should be pruned from the walk.
+  /// The walk's root is never ignored.
+  pub fn is_ignored(&mut self, entry: &DirEntry) -> bool {
+    self.check(entry.path(), entry.depth(), entry.file_type().is_dir()).is_some()
+  }
+
+  /// Returns the rule ignoring a path (relative to `root`) or any of its parent directories, if any.
+  /// Loads the ignore files of all directories on the way, like a walk to the path would.
+  pub fn check_path(&mut self, root: &Path, relative_path: &Path, is_dir: bool) -> Option<Glob> {
+    self.check(root, 0, true);
+    let mut path = root.to_path_buf();
+    let num_components = relative_path.components().count();
+    for (i, component) in relative_path.components().enumerate() {
+      path.push(component);
+      let last = i + 1 == num_components;
+      if let Some(glob) = self.check(&path, i + 1, !last || is_dir) {
+        return Some(glob);
+      }
+    }
+    None
+  }
+
+  fn check(&mut self, path: &Path, depth: usize, is_dir: bool) -> Option<Glob> {
+    // matchers of directories the walk has left
+    self.stack.retain(|(d, _)| *d < depth);
+    let ignored = if depth == 0 {None} else {self.matched(path, is_dir)};
+    if ignored.is_none() && is_dir {
+      self.load(path, depth);
+    }
+    ignored
+  }
+
+  /// Returns the innermost rule matching a path, if it ignores the path.
+  fn matched(&self, path: &Path, is_dir: bool) -> Option<Glob> {
+    for (_, gitignore) in self.stack.iter().rev() {
+      match gitignore.matched(path, is_dir) {
+        Match::None => continue,
+        Match::Ignore(glob) => return Some(glob.clone()),
+        Match::Whitelist(_) => return None,
+      }
+    }
+    None
+  }
+
+  /// Reads the ignore files of a directory, if there are any.
+  fn load(&mut self, dir: &Path, depth: usize) {
+    let files: Vec<PathBuf> = self.file_names.iter().map(|name| dir.join(name)).filter(|file| file.is_file()).collect();
+    if files.is_empty() { return; }
+    let mut builder = GitignoreBuilder::new(dir);
+    for file in &files {
+      if let Some(err) = builder.add(file) {
+        self.errors.push(err.to_string());
+      }
+    }
+    match builder.build() {
+      Ok(gitignore) => self.stack.push((depth, gitignore)),
+      Err(err) => self.errors.push(err.to_string()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::test_dir::TestDir;
+  use std::fs;
+  use walkdir::WalkDir;
+
+  /// Creates the given files (with parent directories) below a new test directory.
+  fn tree(name: &str, files: &[(&str, &str)]) -> TestDir {
+    let dir = TestDir::new(name);
+    for (path, content) in files {
+      let path = dir.join(path);
+      fs::create_dir_all(path.parent().unwrap()).unwrap();
+      fs::write(path, content).unwrap();
+    }
+    dir
+  }
+
+  /// Walks the tree like the scanner does and returns the files not ignored, ignore files left out.
+  fn walk(root: &Path, gitignore: bool) -> Vec<String> {
+    let mut ignore_files = IgnoreFiles::new(gitignore);
+    let files = WalkDir::new(root).sort_by_file_name().into_iter()
+      .filter_entry(|e| !ignore_files.is_ignored(e))
+      .map(Result::unwrap)
+      .filter(|e| e.file_type().is_file() && e.file_name() != BACCYIGNORE && e.file_name() != GITIGNORE)
+      .map(|e| e.path().strip_prefix(root).unwrap().display().to_string())
+      .collect();
+    assert!(ignore_files.errors.is_empty(), "{:?}", ignore_files.errors);
+    files
+  }
+
+  #[test]
+  fn deeper_files_re_include() {
+    let dir = tree("ignore-negation", &[
+      (".baccyignore", "*.log\n"),
+      ("a.log", ""),
+      ("sub/.baccyignore", "!keep.log\n"),
+      ("sub/keep.log", ""),
+      ("sub/other.log", ""),
+      ("sub/file.txt", ""),
+    ]);
+    assert_eq!(walk(&dir, false), ["sub/file.txt", "sub/keep.log"]);
+  }
+
+  #[test]
+  fn anchors_rules_to_their_directory() {
+    let dir = tree("ignore-anchored", &[
+      (".baccyignore", "/build\nsub/cache/\n"),
+      ("build/out", ""),
+      ("sub/build/out", ""),
+      ("sub/cache/file", ""),
+      ("sub/.baccyignore", "/only-here\n"),
+      ("sub/only-here", ""),
+      ("sub/deeper/only-here", ""),
+      ("only-here", ""),
+    ]);
+    assert_eq!(walk(&dir, false), ["only-here", "sub/build/out", "sub/deeper/only-here"]);
+  }
+
+  #[test]
+  fn drops_rules_when_leaving_their_directory() {
+    let dir = tree("ignore-scope", &[
+      ("a/.baccyignore", "*.tmp\n"),
+      ("a/x.tmp", ""),
+      ("a/deeper/y.tmp", ""),
+      ("b/x.tmp", ""),
+      ("c.tmp", ""),
+    ]);
+    // applies to the whole subtree of "a", but neither to its siblings nor its parent
+    assert_eq!(walk(&dir, false), ["b/x.tmp", "c.tmp"]);
+  }
+
+  #[test]
+  fn reads_gitignore_only_if_enabled() {
+    let dir = tree("ignore-gitignore", &[
+      (".gitignore", "*.o\nkept\n"),
+      (".baccyignore", "!kept\n"),
+      ("main.o", ""),
+      ("kept", ""),
+    ]);
+    assert_eq!(walk(&dir, false), ["kept", "main.o"]);
+    // .baccyignore takes precedence over .gitignore in the same directory
+    assert_eq!(walk(&dir, true), ["kept"]);
+
+    let mut ignore_files = IgnoreFiles::new(true);
+    let glob = ignore_files.check_path(&dir, Path::new("main.o"), false).unwrap();
+    assert_eq!(glob.original(), "*.o");
+    assert!(ignore_files.check_path(&dir, Path::new("kept"), false).is_none());
+  }
+}
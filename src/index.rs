@@ -0,0 +1,177 @@
+use std::{collections::HashMap, fs::{self, Metadata}, io, path::{Path, PathBuf}};
+
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+
+use crate::compare::FileState;
+
+/// Directory in the target holding baccy's own data. Never copied, deleted or mirrored.
+pub const META_DIR: &str = ".baccy";
+const INDEX_FILE: &str = "index";
+
+/// State of a single file in the target, as left behind by the last run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+  pub bytes: u64,
+  pub mtime: i64,
+  pub mtime_nanos: u32,
+  /// Inode number on Unix; always 0 on other platforms.
+  pub inode: u64,
+  /// Hex-encoded BLAKE3 hash of the content, if it was ever computed.
+  pub hash: Option<String>,
+  /// Permission bits, user and group; Unix only. Missing in indexes written by older versions.
+  #[serde(default)]
+  pub mode: Option<u32>,
+  #[serde(default)]
+  pub uid: Option<u32>,
+  #[serde(default)]
+  pub gid: Option<u32>,
+}
+
+impl IndexEntry {
+  pub fn from_metadata(metadata: &Metadata, hash: Option<blake3::Hash>) -> Self {
+    let mtime = FileTime::from_last_modification_time(metadata);
+    #[cfg(unix)]
+    let (inode, mode, uid, gid) = {
+      use std::os::unix::fs::MetadataExt;
+      (metadata.ino(), Some(metadata.mode() & 0o7777), Some(metadata.uid()), Some(metadata.gid()))
+    };
+    #[cfg(not(unix))]
+    let (inode, mode, uid, gid) = (0, None, None, None);
+    Self {
+      bytes: metadata.len(),
+      mtime: mtime.unix_seconds(),
+      mtime_nanos: mtime.nanoseconds(),
+      inode,
+      hash: hash.map(|h| h.to_hex().to_string()),
+      mode,
+      uid,
+      gid,
+    }
+  }
+
+  pub fn state(&self) -> FileState {
+    FileState {
+      bytes: self.bytes,
+      mtime: FileTime::from_unix_time(self.mtime, self.mtime_nanos),
+    }
+  }
+
+  pub fn hash(&self) -> Option<blake3::Hash> {
+    self.hash.as_ref().and_then(|h| blake3::Hash::from_hex(h).ok())
+  }
+}
+
+/// Index of all files in a target, keyed by their path relative to the target.
+/// Lets the scanner compare against the last run's results instead of stat-ing (or hashing) every target file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Index {
+  /// Name of the snapshot the files are in, if written in snapshot mode. Missing in indexes written by older versions.
+  #[serde(default)]
+  snapshot: Option<String>,
+  files: HashMap<String, IndexEntry>,
+}
+
+impl Index {
+  /// Creates an empty index for the files of a target, or of one of its snapshots.
+  pub fn new(snapshot: Option<&Path>) -> Self {
+    Self {
+      snapshot: snapshot.and_then(|dir| dir.file_name()).map(|name| name.to_string_lossy().into_owned()),
+      files: HashMap::new(),
+    }
+  }
+
+  /// Returns true if the index lists the files of the given snapshot, or of the target itself if None.
+  pub fn describes(&self, snapshot: Option<&Path>) -> bool {
+    self.snapshot.as_deref() == snapshot.and_then(|dir| dir.file_name()).map(|name| name.to_string_lossy()).as_deref()
+  }
+
+  fn path(target: &Path) -> PathBuf {
+    target.join(META_DIR).join(INDEX_FILE)
+  }
+
+  /// Reads the index of a target. A missing or unreadable index is treated as empty.
+  pub fn load(target: &Path) -> Self {
+    fs::read_to_string(Self::path(target)).ok()
+      .and_then(|s| serde_json::from_str(&s).ok())
+      .unwrap_or_default()
+  }
+
+  /// Writes the index into the target, replacing the previous one.
+  pub fn save(&self, target: &Path) -> io::Result<()> {
+    let path = Self::path(target);
+    fs::create_dir_all(path.parent().unwrap())?;
+    // write to a temporary file first, so an interrupted write never leaves a broken index
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_string(self)?)?;
+    fs::rename(tmp_path, path)
+  }
+
+  pub fn get(&self, relative: &str) -> Option<&IndexEntry> {
+    self.files.get(relative)
+  }
+
+  pub fn insert(&mut self, relative: String, entry: IndexEntry) {
+    self.files.insert(relative, entry);
+  }
+
+  /// Inserts an entry for a file in the target, reading its metadata.
+  pub fn insert_from_path(&mut self, relative: String, path: &Path, hash: Option<blake3::Hash>) {
+    if let Ok(metadata) = fs::metadata(path) {
+      self.insert(relative, IndexEntry::from_metadata(&metadata, hash));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::test_dir::TestDir;
+
+  #[test]
+  fn round_trips_through_the_target() {
+    let target = TestDir::new("index");
+    let file = target.join("file.txt");
+    fs::write(&file, b"content").unwrap();
+
+    let hash = blake3::hash(b"content");
+    let mut index = Index::new(Some(Path::new("/target/2026-01-01_00-00-00")));
+    index.insert_from_path(String::from("dir/file.txt"), &file, Some(hash));
+    index.save(&target).unwrap();
+    let loaded = Index::load(&target);
+
+    let saved = index.get("dir/file.txt").unwrap();
+    let entry = loaded.get("dir/file.txt").unwrap();
+    assert_eq!(entry.bytes, 7);
+    assert_eq!((entry.mtime, entry.mtime_nanos, entry.inode), (saved.mtime, saved.mtime_nanos, saved.inode));
+    assert_eq!((entry.mode, entry.uid, entry.gid), (saved.mode, saved.uid, saved.gid));
+    assert_eq!(entry.hash(), Some(hash));
+    assert!(loaded.get("file.txt").is_none());
+    assert!(loaded.describes(Some(Path::new("2026-01-01_00-00-00"))));
+    assert!(!loaded.describes(Some(Path::new("2026-01-02_00-00-00"))));
+    assert!(!loaded.describes(None));
+  }
+
+  #[test]
+  fn treats_missing_or_broken_indexes_as_empty() {
+    let dir = TestDir::new("index-broken");
+    let target = dir.join("target");
+    assert!(Index::load(&target).files.is_empty());
+    fs::create_dir_all(target.join(META_DIR)).unwrap();
+    fs::write(Index::path(&target), "{ not json").unwrap();
+    let loaded = Index::load(&target);
+    assert!(loaded.files.is_empty());
+  }
+
+  #[test]
+  fn reads_entries_without_mode_and_owner() {
+    let index: Index = serde_json::from_str(
+      r#"{"files":{"a":{"bytes":1,"mtime":2,"mtime_nanos":3,"inode":4,"hash":null}}}"#
+    ).unwrap();
+    let entry = index.get("a").unwrap();
+    assert_eq!((entry.bytes, entry.mtime, entry.mtime_nanos, entry.inode), (1, 2, 3, 4));
+    assert_eq!((entry.mode, entry.uid, entry.gid), (None, None, None));
+    assert_eq!(entry.hash(), None);
+    assert!(index.describes(None));
+  }
+}
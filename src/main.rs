@@ -1,34 +1,57 @@
 use std::{
-  fs, process, io::{self, Write}
+  process, io::{self, Write}
 };
 
-use clap::Parser;
 use colored::Colorize;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use sysinfo::Disks;
 
 use crate::{
-  config::{cli::Arguments, json::JSONConfig},
+  config::{cli::{self, Command, Invocation}, json::JSONConfig},
+  run::CompiledRules,
   util::{normalize_drive::normalize_drive, run_command::run_command}
 };
 
+mod compare;
 mod config;
+mod delta;
+mod diff;
+mod explain;
+mod filter;
+mod ignore_files;
+mod index;
+mod metadata;
 mod progress_helpers;
+mod prune;
+mod restore;
 mod run;
 mod scanner;
+mod snapshot;
 mod task_copy_delete;
+mod tree_diff;
 mod util;
+mod verify;
 
-// Represents a copy or delete task
+// Represents a copy, link, symlink or delete task
 enum Task {
   Copy(task_copy_delete::Copy),
+  Link(task_copy_delete::Link),
+  HardLink(task_copy_delete::HardLink),
+  Symlink(task_copy_delete::Symlink),
+  UpdateMetadata(task_copy_delete::UpdateMetadata),
   Delete(task_copy_delete::Delete),
+  Cleanup(task_copy_delete::Cleanup),
 }
 impl Task {
   fn relative(&self) -> &String {
     match self {
       Task::Copy(c) => &c.relative,
+      Task::Link(l) => &l.relative,
+      Task::HardLink(h) => &h.relative,
+      Task::Symlink(s) => &s.relative,
+      Task::UpdateMetadata(m) => &m.relative,
       Task::Delete(d) => &d.relative,
+      Task::Cleanup(c) => &c.relative,
     }
   }
 }
@@ -36,73 +59,67 @@ impl Task {
 pub const CHANNEL_CAPACITY: usize = 10000;
 
 fn main() {
-  let args = Arguments::parse();
+  let args = match cli::parse() {
+    Invocation::Sync(args) => *args,
+    Invocation::Command(command) => {
+      match *command {
+        Command::Prune { config, operation, dry_run } => prune::prune_command(&config, operation, dry_run),
+        Command::Verify { source, target, operation, checksum, json } =>
+          verify::verify_command(&source, target, operation, checksum, json),
+        Command::Diff { source, target, operation } => diff::diff_command(&source, target, operation),
+        Command::Explain { config, path, operation, rules } => explain::explain_command(&config, &path, operation, *rules),
+        Command::Restore { config, operation, path, to, snapshot, delete, dry_run } =>
+          restore::restore_command(&config, operation, path, to, snapshot, delete, dry_run),
+      }
+      return;
+    }
+  };
   // dbg!(&args);
   if args.is_json_config() {
     // JSON config: read and parse
-    let config = fs::read_to_string(&args.source).unwrap_or_else(|err| {
-      eprintln!("Failed to read config file '{}': {}", args.source.display(), err);
-      process::exit(1);
-    });
-    let mut config = serde_json::from_str::<JSONConfig>(&config).unwrap_or_else(|err| {
-      eprintln!("Failed to parse JSON config: {}", err);
-      process::exit(1);
-    });
+    let mut config = JSONConfig::read(&args.source);
 
-    // merge CLI excludes into JSON config
-    let merge_sort_dedup = |a: &Vec<String>, b: &Vec<String>| {
-      let mut out = a.iter().chain(b.iter()).cloned().collect::<Vec<_>>();
-      out.sort();
-      out.dedup();
-      out
-    };
-    config.exclude_dirs = merge_sort_dedup(&config.exclude_dirs, &args.exclude_dirs);
-    config.exclude_files = merge_sort_dedup(&config.exclude_files, &args.exclude_files);
-    config.exclude_patterns = merge_sort_dedup(&config.exclude_patterns, &args.exclude_patterns);
-    
-    // merge CLI includes into JSON config
-    config.include_dirs = merge_sort_dedup(&config.include_dirs, &args.include_dirs);
-    config.include_files = merge_sort_dedup(&config.include_files, &args.include_files);
-    config.include_patterns = merge_sort_dedup(&config.include_patterns, &args.include_patterns);
-
-    // merge CLI force-includes into JSON config
-    config.force_include_dirs = merge_sort_dedup(&config.force_include_dirs, &args.force_include_dirs);
-    config.force_include_files = merge_sort_dedup(&config.force_include_files, &args.force_include_files);
-    config.force_include_patterns = merge_sort_dedup(&config.force_include_patterns, &args.force_include_patterns);
+    // merge CLI rules into JSON config
+    config.rules.merge(&args.rules);
 
     // dbg!(&config);
-    // run operations in loop
-    let mut i = 0;
-    let num_ops = config.operations.len();
-    for mut op in config.operations {
-      i += 1;
-      // merge global excludes with op-specific
-      op.exclude_dirs = merge_sort_dedup(&op.exclude_dirs, &config.exclude_dirs);
-      op.exclude_files = merge_sort_dedup(&op.exclude_files, &config.exclude_files);
-      op.exclude_patterns = merge_sort_dedup(&op.exclude_patterns, &config.exclude_patterns);
-      // merge global includes with op-specific
-      op.include_dirs = merge_sort_dedup(&op.include_dirs, &config.include_dirs);
-      op.include_files = merge_sort_dedup(&op.include_files, &config.include_files);
-      op.include_patterns = merge_sort_dedup(&op.include_patterns, &config.include_patterns);
-      // merge global force-includes with op-specific
-      op.force_include_dirs = merge_sort_dedup(&op.force_include_dirs, &config.force_include_dirs);
-      op.force_include_files = merge_sort_dedup(&op.force_include_files, &config.force_include_files);
-      op.force_include_patterns = merge_sort_dedup(&op.force_include_patterns, &config.force_include_patterns);
-      
+    // apply CLI options to all operations
+    let mut operations = config.take_operations(None);
+    for op in &mut operations {
+      if args.mode.is_some() { op.mode = args.mode }
+      if args.retention.is_set() { op.retention = args.retention.clone() }
+      if args.compare.is_some() { op.compare = args.compare }
+      if args.symlinks.is_some() { op.symlinks = args.symlinks }
+      if args.preserve.is_some() { op.preserve = args.preserve.clone() }
+      if config.index { op.index = true }
+      if args.index { op.index = true }
+      if config.verify { op.verify = true }
+      if args.verify { op.verify = true }
+      if args.delta_threshold.is_some() { op.delta_threshold = args.delta_threshold }
+      if args.backup_dir.is_some() { op.backup_dir = args.backup_dir.clone() }
       if args.no_delete { op.no_delete = true }
+      if config.dry_run { op.dry_run = true }
+      if args.dry_run { op.dry_run = true }
       if config.log_files { op.log_files = true }
       if args.log_files { op.log_files = true }
       if config.log_rules { op.log_rules = true }
       if args.log_rules { op.log_rules = true }
-      
+    }
+    // every operation's rules are checked before the first one runs
+    let rules: Vec<CompiledRules> = operations.iter().map(CompiledRules::compile).collect();
+
+    // run operations in loop
+    let num_ops = operations.len();
+    let mut num_verify_failed = 0;
+    for (i, (op, rules)) in operations.into_iter().zip(rules).enumerate() {
       println!();
       //dbg!(&op);
-      run::run(op, format!(" {} / {} ", i, num_ops));
+      num_verify_failed += run::run(op, rules, format!(" {} / {} ", i + 1, num_ops));
     }
     println!();
     println!("Completed {} operations.", num_ops);
 
-    if config.drive_info.len() > 0 {
+    if !config.drive_info.is_empty() {
       // normalize drive paths
       config.drive_info = config.drive_info.iter().map(|d| normalize_drive(d.to_string())).collect();
       println!();
@@ -113,10 +130,9 @@ fn main() {
       let infos = MultiProgress::new();
       for disk in Disks::new_with_refreshed_list().iter() {
         // check if drive mount point is in provided list of mount points to print
-        if let Some(mount_str) = disk.mount_point().to_str() {
-          if !config.drive_info.contains(&mount_str.to_string()) {
-            continue;
-          }
+        if let Some(mount_str) = disk.mount_point().to_str()
+          && !config.drive_info.contains(&mount_str.to_string()) {
+          continue;
         }
         // prepare progress bar
         let info = infos.add(ProgressBar::new(disk.total_space()));
@@ -130,7 +146,7 @@ fn main() {
       }
     }
 
-    if config.post_commands.len() > 0 {
+    if !config.post_commands.is_empty() {
       println!();
       for cmd in config.post_commands {
         println!("Running command: {}", cmd.dimmed());
@@ -150,10 +166,17 @@ fn main() {
     }
 
     println!();
+    if num_verify_failed > 0 {
+      process::exit(1);
+    }
   } else {
     // Not in JSON-config-mode, just run on arguments
     println!();
-    run::run(args, String::from(""));
+    let rules = CompiledRules::compile(&args);
+    let num_verify_failed = run::run(args, rules, String::from(""));
     println!();
+    if num_verify_failed > 0 {
+      process::exit(1);
+    }
   }
 }
\ No newline at end of file
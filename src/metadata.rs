@@ -0,0 +1,199 @@
+use std::{collections::{BTreeSet, HashSet}, fs::{self, Metadata}, io, path::{Path, PathBuf}, sync::Mutex};
+
+use filetime::FileTime;
+
+use crate::{config::preserve::Preserve, index::{IndexEntry, META_DIR}, task_copy_delete::TEMP_PREFIX};
+
+/// Extended attributes holding POSIX ACLs; copied with "acls", all others with "xattrs".
+#[cfg(unix)]
+const ACL_XATTRS: [&str; 2] = ["system.posix_acl_access", "system.posix_acl_default"];
+
+/// File in the target's meta directory listing the extended attributes that couldn't be set on its files.
+const SKIPPED_XATTRS_FILE: &str = "skipped-xattrs.json";
+
+/// Names of extended attributes that couldn't be set in this run, though the target supports extended attributes
+/// (eg. SELinux labels the process may not change).
+static FAILED_XATTRS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+fn skipped_xattrs_path(target: &Path) -> PathBuf {
+  target.join(META_DIR).join(SKIPPED_XATTRS_FILE)
+}
+
+/// Loads the extended attributes that couldn't be set on a target's files in earlier runs. They're left out when
+/// comparing metadata: files would be updated again on every run, as their copies never get them.
+pub fn load_skipped_xattrs(target: &Path) -> HashSet<String> {
+  fs::read_to_string(skipped_xattrs_path(target)).ok()
+    .and_then(|json| serde_json::from_str(&json).ok())
+    .unwrap_or_default()
+}
+
+/// Adds the extended attributes that couldn't be set in this run to the ones skipped before, and saves them.
+pub fn save_skipped_xattrs(target: &Path, skipped: &HashSet<String>) -> io::Result<()> {
+  let failed = FAILED_XATTRS.lock().unwrap();
+  if failed.iter().all(|name| skipped.contains(name)) {
+    return Ok(());
+  }
+  let all: BTreeSet<&String> = skipped.iter().chain(failed.iter()).collect();
+  fs::create_dir_all(target.join(META_DIR))?;
+  fs::write(skipped_xattrs_path(target), serde_json::to_string(&all)?)
+}
+
+/// Returns true if an error means the file system doesn't support extended attributes (or ACLs).
+#[cfg(unix)]
+fn is_unsupported(err: &io::Error) -> bool {
+  err.kind() == io::ErrorKind::Unsupported || err.raw_os_error() == Some(libc::ENOTSUP)
+}
+
+/// Checks whether the file system of a target supports the preserved extended attributes and ACLs, by writing
+/// a temporary file into the target (or its nearest existing parent). Returns what can be preserved, with
+/// warnings about what can't; the sync goes on without them.
+pub fn check_support(target: &Path, preserve: Preserve) -> (Preserve, Vec<String>) {
+  let mut supported = preserve;
+  let mut warnings = Vec::new();
+  if !preserve.xattrs && !preserve.acls {
+    return (supported, warnings);
+  }
+  #[cfg(unix)]
+  {
+    let Some(dir) = target.ancestors().find(|dir| dir.is_dir()) else { return (supported, warnings) };
+    let probe = dir.join(format!("{}xattr-probe", TEMP_PREFIX));
+    if fs::write(&probe, []).is_err() {
+      return (supported, warnings);
+    }
+    if preserve.xattrs && xattr::set(&probe, "user.baccy-probe", b"1").is_err_and(|err| is_unsupported(&err)) {
+      supported.xattrs = false;
+      warnings.push(format!("{} doesn't support extended attributes; they are not preserved.", dir.display()));
+    }
+    if preserve.acls && xattr::get(&probe, ACL_XATTRS[0]).is_err_and(|err| is_unsupported(&err)) {
+      supported.acls = false;
+      warnings.push(format!("{} doesn't support ACLs; they are not preserved.", dir.display()));
+    }
+    let _ = fs::remove_file(&probe);
+  }
+  #[cfg(not(unix))]
+  {
+    let _ = target;
+    supported.xattrs = false;
+    supported.acls = false;
+    warnings.push(String::from("Extended attributes and ACLs are only preserved on Unix."));
+  }
+  (supported, warnings)
+}
+
+/// Returns true if the process can change the owner of files.
+fn is_root() -> bool {
+  #[cfg(unix)]
+  return unsafe { libc::geteuid() } == 0;
+  #[cfg(not(unix))]
+  return false;
+}
+
+/// Applies the preserved metadata of a source file to its copy. The modification time is always copied.
+/// Everything is attempted; the first error is returned.
+pub fn apply(from: &Path, metadata: &Metadata, to: &Path, preserve: Preserve) -> io::Result<()> {
+  apply_with_mtime(from, metadata, to, preserve, true)
+}
+
+/// Applies the preserved metadata of a source directory to its copy, after its contents have been synced.
+/// Unlike files, the modification time is only copied with "times".
+pub fn apply_to_dir(from: &Path, to: &Path, preserve: Preserve) -> io::Result<()> {
+  apply_with_mtime(from, &fs::metadata(from)?, to, preserve, preserve.times)
+}
+
+fn apply_with_mtime(from: &Path, metadata: &Metadata, to: &Path, preserve: Preserve, copy_mtime: bool) -> io::Result<()> {
+  let mut result = Ok(());
+  let mut attempt = |res: io::Result<()>| if result.is_ok() { result = res; };
+
+  // the owner first: changing it clears setuid and setgid bits
+  #[cfg(unix)]
+  if preserve.owner && is_root() {
+    use std::os::unix::fs::MetadataExt;
+    attempt(std::os::unix::fs::chown(to, Some(metadata.uid()), Some(metadata.gid())));
+  }
+  #[cfg(unix)]
+  if preserve.xattrs || preserve.acls {
+    attempt(copy_xattrs(from, to, |name| if is_acl(name) {preserve.acls} else {preserve.xattrs}));
+  }
+  if preserve.mode {
+    attempt(fs::set_permissions(to, metadata.permissions()));
+  }
+  let mtime = FileTime::from_last_modification_time(metadata);
+  if preserve.times {
+    attempt(filetime::set_file_times(to, FileTime::from_last_access_time(metadata), mtime));
+  } else if copy_mtime {
+    attempt(filetime::set_file_mtime(to, mtime));
+  }
+  result
+}
+
+/// Returns true if the preserved metadata of a target file differs from its source, apart from times
+/// (those are up to the compare mode) and the extended attributes in `skipped_xattrs`.
+/// The mode and owner are taken from the target's index entry. Always false on other platforms than Unix.
+pub fn differs(src: &Metadata, from: &Path, dst: &IndexEntry, to: &Path, preserve: Preserve, skipped_xattrs: &HashSet<String>) -> bool {
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::MetadataExt;
+    let differs = |src: u32, dst: Option<u32>| dst.is_some_and(|dst| dst != src);
+    if preserve.mode && differs(src.mode() & 0o7777, dst.mode) { return true; }
+    if preserve.owner && is_root() && (differs(src.uid(), dst.uid) || differs(src.gid(), dst.gid)) { return true; }
+    let selected = |name: &std::ffi::OsStr|
+      !skipped_xattrs.contains(&*name.to_string_lossy()) && if is_acl(name) {preserve.acls} else {preserve.xattrs};
+    if (preserve.xattrs || preserve.acls) && read_xattrs(from, selected).ok() != read_xattrs(to, selected).ok() {
+      return true;
+    }
+    false
+  }
+  #[cfg(not(unix))]
+  {
+    let _ = (src, from, dst, to, preserve, skipped_xattrs);
+    false
+  }
+}
+
+#[cfg(unix)]
+fn is_acl(name: &std::ffi::OsStr) -> bool {
+  ACL_XATTRS.iter().any(|acl| name == *acl)
+}
+
+/// Reads the extended attributes of a file selected by name, sorted by name.
+/// A file system without extended attributes has none.
+#[cfg(unix)]
+fn read_xattrs(path: &Path, selected: impl Fn(&std::ffi::OsStr) -> bool) -> io::Result<Vec<(std::ffi::OsString, Vec<u8>)>> {
+  let mut xattrs = Vec::new();
+  let names = match xattr::list(path) {
+    Ok(names) => names,
+    Err(err) if is_unsupported(&err) => return Ok(xattrs),
+    Err(err) => return Err(err),
+  };
+  for name in names.filter(|name| selected(name)) {
+    if let Some(value) = xattr::get(path, &name)? {
+      xattrs.push((name, value));
+    }
+  }
+  xattrs.sort();
+  Ok(xattrs)
+}
+
+/// Copies the selected extended attributes, removing selected attributes the source doesn't have.
+/// Every attribute is attempted; the first error is returned, and the names of the attributes that failed are kept
+/// (see `save_skipped_xattrs`).
+#[cfg(unix)]
+fn copy_xattrs(from: &Path, to: &Path, selected: impl Fn(&std::ffi::OsStr) -> bool) -> io::Result<()> {
+  let xattrs = read_xattrs(from, &selected)?;
+  let mut result = Ok(());
+  let mut attempt = |name: &std::ffi::OsStr, res: io::Result<()>| if let Err(err) = res {
+    FAILED_XATTRS.lock().unwrap().insert(name.to_string_lossy().to_string());
+    if result.is_ok() {
+      result = Err(io::Error::new(err.kind(), format!("extended attribute {}: {}", name.to_string_lossy(), err)));
+    }
+  };
+  for name in xattr::list(to)?.filter(|name| selected(name)) {
+    if !xattrs.iter().any(|(n, _)| *n == name) {
+      attempt(&name, xattr::remove(to, &name));
+    }
+  }
+  for (name, value) in &xattrs {
+    attempt(name, xattr::set(to, name, value));
+  }
+  result
+}
@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 
 pub const PROGERSS_BAR_TASK: &str = "##-";
 pub const PROGERSS_BAR_FILE: &str = "=> ";
@@ -30,4 +30,14 @@ pub fn setup_spinner(progress: &mut ProgressBar, msg: &'static str) {
 pub fn finish_progress(progress: ProgressBar, msg: String) {
   progress.set_style(spinner_style());
   progress.finish_with_message(msg);
-}
\ No newline at end of file
+}
+
+/// Prints a line above the progress bars; straight to stdout if they aren't drawn (no terminal), where indicatif
+/// would drop it.
+pub fn println(progress: &MultiProgress, line: String) {
+  if progress.is_hidden() {
+    println!("{}", line);
+  } else {
+    let _ = progress.println(line);
+  }
+}
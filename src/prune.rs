@@ -0,0 +1,241 @@
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
+
+use colored::Colorize;
+use indicatif::ProgressBar;
+use walkdir::WalkDir;
+
+use crate::{
+  config::{json::JSONConfig, retention::Retention, sync_mode::SyncMode},
+  progress_helpers::setup_spinner,
+  snapshot::{list_snapshots, parse_snapshot_name, update_pointers},
+  util::bytes_to_string::bytes_to_string
+};
+
+/// Runs the "prune" subcommand on all snapshot-mode operations (or the named one) of a JSON config.
+pub fn prune_command(config: &Path, operation: Option<String>, dry_run: bool) {
+  let operations = JSONConfig::read(config).take_operations(operation.as_deref());
+  for op in operations {
+    let Some(target) = op.target else { continue };
+    if op.mode.unwrap_or_default() != SyncMode::Snapshot { continue; }
+    println!();
+    prune(&target, &op.retention, dry_run);
+  }
+  println!();
+}
+
+/// Returns the snapshots kept by the retention rules, with the names of the rules keeping them.
+/// Expects snapshot names sorted oldest first. The newest snapshot is always kept.
+pub fn snapshots_to_keep(retention: &Retention, snapshots: &[String]) -> HashMap<String, Vec<&'static str>> {
+  let mut kept: HashMap<String, Vec<&'static str>> = HashMap::new();
+  let newest_first: Vec<&String> = snapshots.iter().rev().collect();
+  if let Some(newest) = newest_first.first() {
+    kept.entry(newest.to_string()).or_default().push("newest");
+  }
+
+  if let Some(n) = retention.keep_last {
+    for name in newest_first.iter().take(n) {
+      kept.entry(name.to_string()).or_default().push("last");
+    }
+  }
+  // Time-based rules: keep the newest snapshot of each of the last n time periods ("buckets") having snapshots
+  let bucket_rules = [
+    (retention.keep_hourly, "%Y-%m-%d %H", "hourly"),
+    (retention.keep_daily, "%Y-%m-%d", "daily"),
+    (retention.keep_weekly, "%G-%V", "weekly"),
+    (retention.keep_monthly, "%Y-%m", "monthly"),
+    (retention.keep_yearly, "%Y", "yearly"),
+  ];
+  for (n, bucket_format, rule) in bucket_rules {
+    let Some(n) = n else { continue };
+    let mut last_bucket = None;
+    let mut num_kept = 0;
+    for name in &newest_first {
+      if num_kept >= n { break; }
+      let Some(time) = parse_snapshot_name(name) else { continue };
+      let bucket = time.format(bucket_format).to_string();
+      if last_bucket.as_ref() != Some(&bucket) {
+        kept.entry(name.to_string()).or_default().push(rule);
+        num_kept += 1;
+        last_bucket = Some(bucket);
+      }
+    }
+  }
+  kept
+}
+
+/// Returns the number of bytes freed by deleting the given directories.
+/// On Unix, files hard-linked from outside these directories don't free any space.
+fn reclaimable_bytes(dirs: &[PathBuf]) -> u64 {
+  let files = dirs.iter()
+    .flat_map(|dir| WalkDir::new(dir).into_iter().filter_map(Result::ok))
+    .filter(|e| e.file_type().is_file())
+    .filter_map(|e| e.metadata().ok());
+
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::MetadataExt;
+    // (device, inode) => (links inside the deleted directories, total links, size)
+    let mut inodes: HashMap<(u64, u64), (u64, u64, u64)> = HashMap::new();
+    for metadata in files {
+      inodes.entry((metadata.dev(), metadata.ino()))
+        .or_insert((0, metadata.nlink(), metadata.len()))
+        .0 += 1;
+    }
+    inodes.values()
+      .filter(|(links_deleted, links_total, _)| links_deleted >= links_total)
+      .map(|(_, _, bytes)| bytes)
+      .sum()
+  }
+  #[cfg(not(unix))]
+  {
+    files.map(|metadata| metadata.len()).sum()
+  }
+}
+
+/// Deletes all snapshots in the target that aren't kept by the retention rules.
+/// Prints the plan first; with dry-run, nothing is deleted.
+pub fn prune(target: &Path, retention: &Retention, dry_run: bool) {
+  let dry_run_warn = if dry_run {" DRY RUN "} else {""};
+  println!("{}", format!(
+    "Prune: {}  {}",
+    dry_run_warn.on_yellow().bold(),
+    target.to_str().unwrap().cyan()
+  ).bold());
+  if !retention.is_set() {
+    println!("No retention rules set, keeping all snapshots.");
+    return;
+  }
+
+  let snapshots = list_snapshots(target);
+  let kept = snapshots_to_keep(retention, &snapshots);
+  let mut to_delete: Vec<PathBuf> = Vec::new();
+  for name in &snapshots {
+    match kept.get(name) {
+      Some(rules) => println!("{:>10}: {} {}", "keep".dimmed().bold(), name, format!("({})", rules.join(", ")).dimmed()),
+      None => {
+        println!("{:>10}: {}", "DEL".bold(), name);
+        to_delete.push(target.join(name));
+      }
+    }
+  }
+  if to_delete.is_empty() {
+    println!("Nothing to prune.");
+    return;
+  }
+
+  let mut progress = ProgressBar::new_spinner();
+  setup_spinner(&mut progress, "Calculating reclaimable space...");
+  let reclaimed = reclaimable_bytes(&to_delete);
+  let mut deleted_count = 0;
+  if !dry_run {
+    progress.set_message("Deleting snapshots...");
+    for dir in &to_delete {
+      match fs::remove_dir_all(dir) {
+        Ok(_) => deleted_count += 1,
+        Err(err) => {
+          progress.println(format!("{}", format!("Failed to delete {}: {}", dir.display(), err).bright_red()));
+        }
+      }
+    }
+    // the newest snapshot is always kept, so this only rewrites the listing
+    if let Some(newest) = snapshots.last() {
+      let _ = update_pointers(target, &target.join(newest));
+    }
+  } else {
+    deleted_count = to_delete.len();
+  }
+  progress.finish_with_message(format!(
+    "Deleted {} snapshots, reclaimed {}.",
+    deleted_count.to_string().cyan(),
+    bytes_to_string(reclaimed).cyan()
+  ));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn names(values: &[&str]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+  }
+
+  /// Names of the kept snapshots, oldest first.
+  fn kept(retention: Retention, snapshots: &[&str]) -> Vec<String> {
+    let mut kept: Vec<String> = snapshots_to_keep(&retention, &names(snapshots)).into_keys().collect();
+    kept.sort();
+    kept
+  }
+
+  #[test]
+  fn keeps_only_the_newest_without_rules() {
+    assert!(snapshots_to_keep(&Retention::default(), &[]).is_empty());
+    let snapshots = ["2025-01-01_00-00-00", "2025-01-02_00-00-00"];
+    assert_eq!(kept(Retention::default(), &snapshots), names(&["2025-01-02_00-00-00"]));
+    let keep_none = Retention{keep_last: Some(0), keep_daily: Some(0), ..Default::default()};
+    assert_eq!(kept(keep_none, &snapshots), names(&["2025-01-02_00-00-00"]));
+  }
+
+  #[test]
+  fn keeps_the_last_n() {
+    let snapshots = ["2025-01-01_00-00-00", "2025-01-02_00-00-00", "2025-01-03_00-00-00"];
+    assert_eq!(
+      kept(Retention{keep_last: Some(2), ..Default::default()}, &snapshots),
+      names(&["2025-01-02_00-00-00", "2025-01-03_00-00-00"])
+    );
+    assert_eq!(kept(Retention{keep_last: Some(3), ..Default::default()}, &snapshots), names(&snapshots));
+    assert_eq!(kept(Retention{keep_last: Some(10), ..Default::default()}, &snapshots), names(&snapshots));
+  }
+
+  #[test]
+  fn keeps_the_newest_of_each_period_at_its_boundaries() {
+    let hours = ["2025-01-01_10-00-00", "2025-01-01_10-59-59", "2025-01-01_11-00-00"];
+    assert_eq!(
+      kept(Retention{keep_hourly: Some(2), ..Default::default()}, &hours),
+      names(&["2025-01-01_10-59-59", "2025-01-01_11-00-00"])
+    );
+
+    let days = ["2025-01-01_12-00-00", "2025-01-01_23-59-59", "2025-01-02_00-00-00"];
+    assert_eq!(
+      kept(Retention{keep_daily: Some(2), ..Default::default()}, &days),
+      names(&["2025-01-01_23-59-59", "2025-01-02_00-00-00"])
+    );
+
+    // ISO weeks: Sunday 2024-12-29 ends week 52 of 2024; Monday 2024-12-30 starts week 1 of 2025
+    let weeks = ["2024-12-29_12-00-00", "2024-12-30_12-00-00", "2025-01-01_12-00-00"];
+    assert_eq!(
+      kept(Retention{keep_weekly: Some(2), ..Default::default()}, &weeks),
+      names(&["2024-12-29_12-00-00", "2025-01-01_12-00-00"])
+    );
+
+    let years = ["2024-06-01_00-00-00", "2024-12-31_23-59-59", "2025-01-01_00-00-00"];
+    assert_eq!(
+      kept(Retention{keep_monthly: Some(2), ..Default::default()}, &years),
+      names(&["2024-12-31_23-59-59", "2025-01-01_00-00-00"])
+    );
+    assert_eq!(
+      kept(Retention{keep_yearly: Some(2), ..Default::default()}, &years),
+      names(&["2024-12-31_23-59-59", "2025-01-01_00-00-00"])
+    );
+  }
+
+  #[test]
+  fn counts_only_periods_having_snapshots() {
+    // a gap of months doesn't use up the daily rule
+    let snapshots = ["2024-01-01_00-00-00", "2024-06-01_00-00-00", "2025-01-01_00-00-00"];
+    assert_eq!(kept(Retention{keep_daily: Some(3), ..Default::default()}, &snapshots), names(&snapshots));
+    assert_eq!(
+      kept(Retention{keep_daily: Some(2), ..Default::default()}, &snapshots),
+      names(&["2024-06-01_00-00-00", "2025-01-01_00-00-00"])
+    );
+  }
+
+  #[test]
+  fn combines_rules_and_names_them() {
+    let snapshots = names(&["2024-12-31_12-00-00", "2025-01-01_11-00-00", "2025-01-01_12-00-00"]);
+    let retention = Retention{keep_last: Some(1), keep_daily: Some(2), keep_yearly: Some(2), ..Default::default()};
+    let kept = snapshots_to_keep(&retention, &snapshots);
+    assert_eq!(kept.len(), 2);
+    assert_eq!(kept["2025-01-01_12-00-00"], vec!["newest", "last", "daily", "yearly"]);
+    assert_eq!(kept["2024-12-31_12-00-00"], vec!["daily", "yearly"]);
+  }
+}
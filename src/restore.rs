@@ -0,0 +1,135 @@
+use std::{path::{Path, PathBuf}, process};
+
+use colored::Colorize;
+use glob::Pattern;
+
+use crate::{
+  config::{compare_mode::CompareMode, json::JSONConfig, retention::Retention, rule_args::RuleArgs, sync_mode::SyncMode},
+  index::META_DIR,
+  run::{self, CompiledRules},
+  snapshot::{latest_snapshot, list_snapshots},
+  task_copy_delete::TEMP_PREFIX
+};
+
+/// Runs the "restore" subcommand: syncs the backups of all operations (or the named one) in a JSON config back
+/// to their sources, or into another directory. Snapshot-mode operations are restored from the newest (or the
+/// given) snapshot. Nothing is deleted unless `delete` is set.
+pub fn restore_command(
+  config: &Path,
+  operation: Option<String>,
+  path: Option<PathBuf>,
+  to: Option<PathBuf>,
+  snapshot: Option<String>,
+  delete: bool,
+  dry_run: bool
+) {
+  let operations = JSONConfig::read(config).take_operations(operation.as_deref());
+  if to.is_some() && operations.len() > 1 {
+    eprintln!("Restoring to another directory needs a single operation; select one with --operation.");
+    process::exit(1);
+  }
+
+  // all operations are prepared (and their rules checked) before the first one runs
+  let mut restores = Vec::new();
+  for mut op in operations {
+    let Some(target) = op.target.take() else { continue };
+    let fail = |msg: String| -> ! {
+      eprintln!("{}", msg.bright_red());
+      process::exit(1);
+    };
+
+    // where the backup is
+    let backup = if op.mode.unwrap_or_default() == SyncMode::Snapshot {
+      match &snapshot {
+        Some(name) if list_snapshots(&target).contains(name) => target.join(name),
+        Some(name) => fail(format!("No snapshot '{}' in {}.", name, target.display())),
+        None => latest_snapshot(&target)
+          .unwrap_or_else(|| fail(format!("No snapshot found in {}.", target.display()))),
+      }
+    } else if snapshot.is_some() {
+      fail(format!("Operation with target {} is not in snapshot mode.", target.display()))
+    } else {
+      target.clone()
+    };
+    let restore_to = to.clone().unwrap_or(op.source.clone());
+
+    // a subpath may be given relative to the source, or as a full path into the source;
+    // operations whose source or backup doesn't contain it are skipped
+    let subpath = match path.as_deref() {
+      Some(p) if p.is_absolute() => match p.strip_prefix(&op.source) {
+        Ok(relative) => Some(relative),
+        Err(_) => {
+          println!("{}", format!("Skipped {}: {} is not in its source.", op.source.display(), p.display()).yellow());
+          continue;
+        }
+      },
+      p => p,
+    };
+    let from = subpath.map(|p| backup.join(p)).unwrap_or(backup.clone());
+    if !from.exists() {
+      println!("{}", format!("Skipped {}: {} not found in backup.", op.source.display(), from.display()).yellow());
+      continue;
+    }
+
+    // reverse direction; a single file is restored by syncing its directory, including only that file
+    let mut include_patterns = Vec::new();
+    let mut single_file = false;
+    if from.is_dir() {
+      op.source = from.clone();
+      op.target = Some(subpath.map(|p| restore_to.join(p)).unwrap_or(restore_to));
+    } else {
+      let file_name = from.file_name().unwrap().to_string_lossy().to_string();
+      include_patterns.push(Pattern::escape(&file_name));
+      single_file = true;
+      op.source = from.parent().unwrap().to_path_buf();
+      op.target = restore_to.join(subpath.unwrap()).parent().map(Path::to_path_buf);
+    }
+
+    // the backup's own files are never restored
+    let mut exclude_patterns = vec![
+      format!("{}/**", Pattern::escape(META_DIR)),
+      format!("**/{}*", Pattern::escape(TEMP_PREFIX)),
+    ];
+    if let Some(backup_dir) = &op.backup_dir
+      && let Ok(relative) = target.join(backup_dir).strip_prefix(&op.source) {
+      exclude_patterns.push(format!("{}/**", Pattern::escape(&relative.to_string_lossy())));
+    }
+
+    // rules were applied when backing up; they don't fit paths relative to a subpath. They still keep
+    // what they didn't include from being deleted, as that was never backed up.
+    let rules = std::mem::replace(&mut op.rules, RuleArgs { exclude_patterns, include_patterns, ..Default::default() });
+    op.protected_rules = Some((rules, subpath.filter(|_| !single_file).unwrap_or(Path::new("")).to_path_buf()));
+
+    // plain mirror into the restored directory, without touching the backup
+    op.mode = None;
+    op.retention = Retention::default();
+    op.index = false;
+    op.delta_threshold = None;
+    op.backup_dir = None;
+    op.no_delete = !delete || single_file;
+    // a damaged file may well look unchanged by its size and modification time
+    op.compare = Some(CompareMode::Checksum);
+    op.dry_run = dry_run;
+    let rules = CompiledRules::compile(&op);
+    restores.push((backup, op, rules));
+  }
+
+  let mut num_verify_failed = 0;
+  let num_restored = restores.len();
+  for (backup, op, rules) in restores {
+    println!();
+    println!("Restoring from {}", backup.display().to_string().cyan());
+    num_verify_failed += run::run(op, rules, String::from(" Restore "));
+  }
+  println!();
+  if num_restored == 0 {
+    eprintln!("{}", match &path {
+      Some(path) => format!("Nothing restored: no backup contains {}.", path.display()),
+      None => String::from("Nothing restored: no backup found."),
+    }.bright_red());
+    process::exit(1);
+  }
+  if num_verify_failed > 0 {
+    process::exit(1);
+  }
+}
@@ -1,7 +1,7 @@
 use std::{
-  collections::{HashSet, VecDeque}, fs, path::PathBuf, sync::{
+  collections::{HashSet, VecDeque}, fs, path::PathBuf, process, sync::{
     atomic::{AtomicU64, AtomicUsize, Ordering},
-    Arc,
+    Arc, Mutex,
   }, thread, time::{Duration, Instant}
 };
 
@@ -10,62 +10,144 @@ use crossbeam::channel::bounded;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use walkdir::WalkDir;
 
-use crate::{config::cli::Arguments, progress_helpers::{
+use crate::{config::{cli::Arguments, preserve::Preserve, sync_mode::SyncMode}, metadata, filter::{describe_limits, Filter}, delta, index::{Index, META_DIR}, progress_helpers::{self, 
   finish_progress, setup_spinner, PROGERSS_BAR_TASK
-}, scanner, util::bytes_to_string::bytes_to_string, Task, CHANNEL_CAPACITY};
+}, prune, scanner, snapshot, util::{bytes_to_string::bytes_to_string, timestamp::timestamp}, Task, CHANNEL_CAPACITY};
+
+/// Rules of an operation, compiled: what's synced, and what's kept from being deleted (see `Arguments::protected_rules`).
+pub struct CompiledRules {
+  filter: Filter,
+  protected: Option<(Filter, PathBuf)>,
+}
+
+impl CompiledRules {
+  /// Validates all patterns and compiles all regexes of an operation. Exits on invalid ones; all operations'
+  /// rules are compiled before any of them runs, so none is left out after others have changed their targets.
+  pub fn compile(args: &Arguments) -> Self {
+    let new_filter = |rules| Filter::new(rules).unwrap_or_else(|err| {
+      eprintln!("{}", format!("Operation {}: {}",
+        args.name.clone().unwrap_or_else(|| args.source.display().to_string()),
+        err
+      ).bright_red());
+      process::exit(1);
+    });
+    Self {
+      filter: new_filter(&args.rules),
+      protected: args.protected_rules.as_ref().map(|(rules, base)| (new_filter(rules), base.clone())),
+    }
+  }
+}
 
 /// Executes an operation, iterating through its tasks.
-pub fn run(args: Arguments, step_prefix: String) {
-  let target = if args.target.is_some() {
-    args.target.unwrap()
+/// Returns the number of files that failed verification.
+pub fn run(args: Arguments, rules: CompiledRules, step_prefix: String) -> usize {
+  let target = match args.target {
+    Some(target) => target,
+    None => panic!("Target path cannot be None on execution.")
+  };
+  // In snapshot mode, files are written into a new snapshot directory and compared against the previous one.
+  // Snapshots always start out empty, so there is nothing to delete.
+  let snapshot = args.mode.unwrap_or_default() == SyncMode::Snapshot;
+  let (dst, link_dest) = if snapshot {
+    (snapshot::new_snapshot(&target), snapshot::latest_snapshot(&target))
   } else {
-    panic!("Target path cannot be None on execution.");
+    (target.clone(), None)
   };
+  let no_delete = args.no_delete || snapshot;
+  // Delta updates modify the existing file, which must be kept in snapshot mode (hard-linked) or with a backup dir.
+  let delta_threshold = args.delta_threshold.filter(|_| !snapshot && args.backup_dir.is_none());
+  // Files whose delta update was interrupted by an earlier run get updated again; markers of files gone since are dropped
+  let mut interrupted = if snapshot { HashSet::new() } else { delta::interrupted(&dst) };
+  if !args.dry_run {
+    interrupted.retain(|relative| {
+      let exists = fs::symlink_metadata(dst.join(relative)).is_ok();
+      if !exists { delta::finish(&dst, relative); }
+      exists
+    });
+  }
+  // Relative backup directories are relative to the target (like rsync's --backup-dir).
+  // Files deleted or overwritten in this run are moved into a trash directory named after the run's start time.
+  let backup_dir = args.backup_dir.as_ref().map(|dir| target.join(dir));
+  let trash = backup_dir.as_ref().map(|dir| dir.join(timestamp()));
+  let deleted_word = if trash.is_some() {"Trashed"} else {"Deleted"};
 
   let no_del_warn = if args.no_delete {" NO DELETE "} else {""};
+  let dry_run_warn = if args.dry_run {" DRY RUN "} else {""};
   println!("{}", format!(
-    "{}    Sync: {}  {} → {}",
+    "{}    {}: {}{}  {} → {}",
     step_prefix.on_cyan(),
+    if snapshot {"Snapshot"} else {"Sync"},
+    dry_run_warn.on_yellow().bold(),
     no_del_warn.on_yellow().bold(),
     args.source.to_str().unwrap().cyan(),
-    target.to_str().unwrap().cyan()
+    dst.to_str().unwrap().cyan()
   ).bold());
+  if snapshot && dst.exists() {
+    println!("{}", format!("Snapshot {} already exists, skipping.", dst.display()).bright_red());
+    return 0;
+  }
+  let CompiledRules { filter, protected } = rules;
 
   // print rules, if set
   // Helper function: logs a Vec<String> if it's not empty.
   let log_rule_vector = |v: &Vec<String>, name: &str| {
-    if v.len() > 0 {println!("{}", format!(
+    if !v.is_empty() {println!("{}", format!(
       "- {}: {}",
       name,
       (v.iter().map(|d| format!("\"{}\"", d)).collect::<Vec<String>>()).join(", ")
     ).dimmed())}
   };
   // Helper function: checks a set of rules
-  let log_rule_set = |name: &str, dirs: &Vec<String>, files: &Vec<String>, patterns: &Vec<String>| {
-    if dirs.len() > 0 || files.len() > 0 || patterns.len() > 0 {
+  let log_rule_set = |name: &str, dirs: &Vec<String>, files: &Vec<String>, patterns: &Vec<String>, regexes: &Vec<String>| {
+    if !dirs.is_empty() || !files.is_empty() || !patterns.is_empty() || !regexes.is_empty() {
       println!("{}", name.bold() );
-      log_rule_vector(&dirs, "Dirs    ");
-      log_rule_vector(&files, "Files   ");
-      log_rule_vector(&patterns, "Patterns");
+      log_rule_vector(dirs, "Dirs    ");
+      log_rule_vector(files, "Files   ");
+      log_rule_vector(patterns, "Patterns");
+      log_rule_vector(regexes, "Regexes ");
     }
   };
   if args.log_rules {
-    log_rule_set("Exclusions:", &args.exclude_dirs, &args.exclude_files, &args.exclude_patterns);
-    log_rule_set("Inclusions:", &args.include_dirs, &args.include_files, &args.include_patterns);
-    log_rule_set("Force-Inclusions:", &args.force_include_dirs, &args.force_include_files, &args.force_include_patterns);
+    let rules = &args.rules;
+    log_rule_set("Exclusions:", &rules.exclude_dirs, &rules.exclude_files, &rules.exclude_patterns, &rules.exclude_regex);
+    log_rule_set("Inclusions:", &rules.include_dirs, &rules.include_files, &rules.include_patterns, &rules.include_regex);
+    log_rule_set(
+      "Force-Inclusions:",
+      &rules.force_include_dirs, &rules.force_include_files, &rules.force_include_patterns, &rules.force_include_regex
+    );
+    let limits = describe_limits(&args.rules);
+    if !limits.is_empty() {
+      println!("{}", "Limits:".bold());
+      for limit in limits {
+        println!("{}", format!("- {}", limit).dimmed());
+      }
+    }
   }
+
+  let mut preserve = Preserve::new(args.preserve.as_deref());
+  // nothing is written in dry runs, so there's nothing to check
+  if !args.dry_run {
+    let (supported, warnings) = metadata::check_support(&dst, preserve);
+    preserve = supported;
+    for warning in warnings {
+      println!("{}", warning.yellow());
+    }
+  }
+  // extended attributes that couldn't be set on the target's files before aren't compared
+  let skipped_xattrs = metadata::load_skipped_xattrs(&target);
   
   // Count total files - progress spinner
   let mut progress = ProgressBar::new_spinner();
   setup_spinner(&mut progress, "Counting files...");
 
-  // Count total files
-  let total_files = WalkDir::new(&args.source)
-    .into_iter()
-    .filter_map(Result::ok)
-    .filter(|e| e.file_type().is_file())
-    .count();
+  // Count total files; ignored and excluded directories are skipped, like the scanner does
+  let mut ignore_files = filter.ignore_files();
+  let symlinks = args.symlinks.unwrap_or_default();
+  let total_files = scanner::walk_source(&args.source, &filter, symlinks, &mut ignore_files, |_| {}, |_| {}).filter(Result::is_ok).count();
   progress.finish_with_message(format!("Found {total_files} files."));
+  for err in &ignore_files.errors {
+    println!("{}", format!("Error in ignore file: {}", err).bright_red());
+  }
 
   // Bounded channel (inter-thread communication): blocks on send() until there is room for the message
   let (tx, rx) = bounded::<Task>(CHANNEL_CAPACITY);
@@ -76,6 +158,15 @@ pub fn run(args: Arguments, step_prefix: String) {
   let num_scanned_delete = Arc::new(AtomicUsize::new(0));
   // This value keeps track of how many files actually need to be copied; for worker progress bar
   let bytes_to_copy_total = Arc::new(AtomicU64::new(0));
+  // New index of the target, filled by scanner (unchanged files) and worker (copied files)
+  // In snapshot mode, the index lists the files of a snapshot: only used if that's the one compared against
+  let index = if args.index { Some(Arc::new(Mutex::new(Index::new(snapshot.then_some(dst.as_path()))))) } else { None };
+  let previous_index = if args.index {
+    // without a previous snapshot, everything is copied into the new one
+    Some(Index::load(&target)).filter(|index| (!snapshot || link_dest.is_some()) && index.describes(link_dest.as_deref()))
+  } else {
+    None
+  };
 
   // Prepare progress
   let progress = MultiProgress::new();
@@ -98,10 +189,14 @@ pub fn run(args: Arguments, step_prefix: String) {
   
   // Scanner thread: processes file metadata and creates tasks for worker thread
   let src_clone = args.source.clone();
-  let dst_clone = target.clone();
+  let dst_clone = dst.clone();
   let num_positive_clone = num_scanned_positive.clone();
   let num_delete_clone = num_scanned_delete.clone();
   let num_bytes_clone = bytes_to_copy_total.clone();
+  let index_clone = index.clone();
+  let backup_dir_clone = backup_dir.clone();
+  let interrupted_clone = interrupted.clone();
+  let skipped_xattrs_clone = skipped_xattrs.clone();
   thread::spawn(move || scanner::scanner(
     src_clone,
     dst_clone,
@@ -110,20 +205,31 @@ pub fn run(args: Arguments, step_prefix: String) {
     num_delete_clone,
     num_bytes_clone,
     &scan_progress,
-    args.exclude_dirs,
-    args.exclude_files,
-    args.exclude_patterns,
-    args.include_dirs,
-    args.include_files,
-    args.include_patterns,
-    args.force_include_dirs,
-    args.force_include_files,
-    args.force_include_patterns,
-    args.no_delete
+    filter,
+    protected,
+    args.compare.unwrap_or_default(),
+    link_dest,
+    backup_dir_clone,
+    previous_index,
+    index_clone,
+    interrupted_clone,
+    skipped_xattrs_clone,
+    symlinks,
+    preserve,
+    no_delete
   ));
 
   let mut is_delete_step = false; // deletes ALWAYS get processed after copies, making this safe
   let mut deleted_count = 0;
+  let mut linked_count = 0;
+  let mut metadata_count = 0;
+  let mut hard_link_count = 0;
+  let mut delta_used = false;
+  let mut sparse_used = false;
+  let mut num_verify_failed: usize = 0;
+  let mut bytes_written: u64 = 0;
+  // In dry-run mode, every task is printed instead of being executed
+  let print_files = args.log_files || args.dry_run;
 
   // Prepare file name logging (regardless if needed)
   let mut filename_buffer: VecDeque<Task> = VecDeque::with_capacity(20);
@@ -133,11 +239,17 @@ pub fn run(args: Arguments, step_prefix: String) {
   // Returns "now" which should be assigned to `last_filename_log`.
   let log_files = |buffer: &mut VecDeque<Task>| {
     for file in buffer.drain(..) {
-      let _ = progress.println(format!(
+      progress_helpers::println(&progress, format!(
         "{:>10}: {}",
         match &file {
           Task::Copy(task) => bytes_to_string(task.bytes).dimmed().bold(),
-          Task::Delete(_) => "DEL".dimmed().bold()
+          Task::Link(_) => "LINK".dimmed().bold(),
+          Task::HardLink(_) => "HLINK".dimmed().bold(),
+          Task::Symlink(_) => "SYMLINK".dimmed().bold(),
+          Task::UpdateMetadata(_) => "META".dimmed().bold(),
+          Task::Delete(_) if trash.is_some() => "TRASH".dimmed().bold(),
+          Task::Delete(_) => "DEL".dimmed().bold(),
+          Task::Cleanup(_) => "TMP".dimmed().bold()
         },
         file.relative().dimmed()
       ));
@@ -151,7 +263,7 @@ pub fn run(args: Arguments, step_prefix: String) {
       task.relative().dimmed()
     ));
 
-    if args.log_files && (
+    if print_files && (
       filename_buffer.len() >= 20 || 
       last_filename_log.elapsed() >= filename_log_interval
     ) { last_filename_log = log_files(&mut filename_buffer); }
@@ -159,46 +271,224 @@ pub fn run(args: Arguments, step_prefix: String) {
     match task {
       Task::Copy(task) => {
         work_progress.set_length(bytes_to_copy_total.load(Ordering::SeqCst));
-        let result = if task.bytes > (1024*1024*50) {
-          task.execute_with_progress(&progress, &work_progress)
-        } else {
-          let res = task.execute();
-          work_progress.inc(task.bytes as u64);
+        let worker_start_pos = work_progress.position();
+        // a delta update is marked as in progress until it (or the copy replacing it) has finished
+        let mut marked = interrupted.contains(&task.relative);
+        // Helper function: copies the file, feeding the source's content into the hasher (if given).
+        // Results in the number of bytes written.
+        let mut copy = |mut hasher: Option<&mut blake3::Hasher>, allow_delta: bool| {
+          let res = if allow_delta && delta_threshold.is_some_and(|threshold| task.bytes >= threshold.0) && task.can_update_in_place() {
+            delta_used = true;
+            marked = true;
+            delta::start(&dst, &task.relative)
+              .and_then(|()| task.execute_delta(&progress, &work_progress, hasher.as_deref_mut()))
+              .or_else(|_| {
+                // the target may be partially updated: replace it completely
+                work_progress.set_position(worker_start_pos);
+                if let Some(hasher) = hasher.as_deref_mut() {
+                  hasher.reset();
+                }
+                task.execute_with_progress(&progress, &work_progress, hasher)
+              })
+          } else if task.bytes > (1024*1024*50) {
+            task.execute_with_progress(&progress, &work_progress, hasher)
+          } else {
+            let res = task.execute(hasher);
+            work_progress.inc(task.bytes);
+            res
+          };
+          res.map(|written| {
+            // holes of sparse files aren't written
+            sparse_used |= written.skipped_holes;
+            if let Some(err) = written.metadata_error {
+              progress_helpers::println(&progress, format!("{}", format!(
+                "Failed to preserve metadata of {}: {}",
+                task.to.display(),
+                err
+              ).yellow()));
+            }
+            written.bytes
+          })
+        };
+
+        let mut source_hash = None;
+        let mut verify_failed = false;
+        let result = if args.dry_run {
+          work_progress.inc(task.bytes);
+          Ok(0)
+        } else if let Some(trash) = &trash && let Err(err) = task.move_existing_to_trash(trash) {
+          // never overwrite a file that couldn't be moved to the trash
+          Err(err)
+        } else if args.verify {
+          // on mismatch, the file is copied once more - completely, in case a delta update went wrong
+          let mut hasher = blake3::Hasher::new();
+          let mut res = copy(Some(&mut hasher), true);
+          if res.is_ok() && !task.verify(&hasher.finalize()).unwrap_or(false) {
+            work_progress.set_position(worker_start_pos);
+            hasher.reset();
+            res = copy(Some(&mut hasher), false);
+            verify_failed = res.is_ok() && !task.verify(&hasher.finalize()).unwrap_or(false);
+          }
+          source_hash = Some(hasher.finalize());
           res
+        } else {
+          copy(None, true)
         };
-        if result.is_err() {
-          let _ = progress.println(format!("{}", format!(
+        if marked && result.is_ok() && !verify_failed {
+          delta::finish(&dst, &task.relative);
+        }
+        if let Ok(written) = result {
+          bytes_written += written;
+          if delta_used || sparse_used {
+            work_progress.set_message(format!("Bytes copied ({} written):", bytes_to_string(bytes_written)));
+          }
+        }
+        if verify_failed {
+          num_verify_failed += 1;
+          // makes the next run copy the file again
+          task.invalidate();
+          progress_helpers::println(&progress, format!("{}", format!(
+            "Verification failed: {} differs from {}",
+            task.to.display(),
+            task.from.display()
+          ).bright_red()));
+        } else if result.is_err() {
+          progress_helpers::println(&progress, format!("{}", format!(
             "Copy failed: {} -> {}",
             task.from.display(),
             task.to.display()
           ).bright_red()));
+        } else if let Some(index) = &index && !args.dry_run {
+          index.lock().unwrap().insert_from_path(task.relative.clone(), &task.to, source_hash);
         }
-        if args.log_files {
+        if print_files {
           filename_buffer.push_back(Task::Copy(task));
         }
       }
+      Task::Link(task) => {
+        if !args.dry_run && task.execute().is_err() {
+          progress_helpers::println(&progress, format!("{}", format!(
+            "Link failed: {} -> {}",
+            task.from.display(),
+            task.to.display()
+          ).bright_red()));
+        }
+        linked_count += 1;
+
+        if print_files {
+          filename_buffer.push_back(Task::Link(task));
+        }
+      }
+      Task::HardLink(task) => {
+        // links that are still intact from the last sync are left alone
+        let linked = task.is_linked();
+        let result = if args.dry_run || linked {
+          Ok(())
+        } else if let Some(trash) = &trash && let Err(err) = task.move_existing_to_trash(trash) {
+          Err(err)
+        } else {
+          task.execute()
+        };
+        match result {
+          Err(_) => {
+            progress_helpers::println(&progress, format!("{}", format!(
+              "Hard link failed: {} -> {}",
+              task.first.display(),
+              task.to.display()
+            ).bright_red()));
+          }
+          Ok(()) => if !args.dry_run && let Some(index) = &index {
+            index.lock().unwrap().insert_from_path(task.relative.clone(), &task.to, None);
+          }
+        }
+
+        if !linked {
+          hard_link_count += 1;
+          if print_files {
+            filename_buffer.push_back(Task::HardLink(task));
+          }
+        }
+      }
+      Task::Symlink(task) => {
+        let result = if args.dry_run {
+          Ok(())
+        } else if let Some(trash) = &trash && let Err(err) = task.move_existing_to_trash(trash) {
+          Err(err)
+        } else {
+          task.execute()
+        };
+        if result.is_err() {
+          progress_helpers::println(&progress, format!("{}", format!(
+            "Symlink failed: {} -> {}",
+            task.from.display(),
+            task.to.display()
+          ).bright_red()));
+        }
+
+        if print_files {
+          filename_buffer.push_back(Task::Symlink(task));
+        }
+      }
+      Task::UpdateMetadata(task) => {
+        if !args.dry_run {
+          match task.execute() {
+            Err(err) => {
+              progress_helpers::println(&progress, format!("{}", format!(
+                "Updating metadata failed: {}: {}",
+                task.to.display(),
+                err
+              ).bright_red()));
+            }
+            Ok(()) => if let Some(index) = &index {
+              index.lock().unwrap().insert_from_path(task.relative.clone(), &task.to, task.hash);
+            }
+          }
+        }
+        metadata_count += 1;
+
+        if print_files {
+          filename_buffer.push_back(Task::UpdateMetadata(task));
+        }
+      }
       Task::Delete(task) => {
         if !is_delete_step {
           is_delete_step = true;
           finish_progress(work_progress, format!(
-            "Copied {} files, {}.",
+            "Copied {} files, {}{}.{}{}",
             num_scanned_positive.load(Ordering::SeqCst).to_string().cyan(),
-            bytes_to_string(bytes_to_copy_total.load(Ordering::SeqCst)).cyan()
+            bytes_to_string(bytes_to_copy_total.load(Ordering::SeqCst)).cyan(),
+            written_note(delta_used || sparse_used, bytes_written),
+            metadata_note(metadata_count),
+            hard_link_note(hard_link_count)
           ));
           work_progress = progress.add(ProgressBar::new_spinner());
           setup_spinner(&mut work_progress, "Deleting files...");
         }
 
-        let _ = fs::remove_file(&task.path);
+        if !args.dry_run && task.execute(trash.as_deref()).is_err() {
+          progress_helpers::println(&progress, format!("{}", format!(
+            "{} failed: {}",
+            if trash.is_some() {"Move to trash"} else {"Delete"},
+            task.path.display()
+          ).bright_red()));
+        }
 
         deleted_count += 1;
         let deleted_count_colored = deleted_count.to_string().cyan();
-        work_progress.set_message(format!("Deleted {} files", deleted_count_colored));
+        work_progress.set_message(format!("{} {} files", deleted_word, deleted_count_colored));
 
-        if args.log_files {
+        if print_files {
           filename_buffer.push_back(Task::Delete(task));
         }
       }
+      Task::Cleanup(task) => {
+        if !args.dry_run && task.execute().is_err() {
+          progress_helpers::println(&progress, format!("{}", format!(
+            "Removing temporary file failed: {}",
+            task.path.display()
+          ).bright_red()));
+        }
+      }
     }
   }
 
@@ -207,56 +497,143 @@ pub fn run(args: Arguments, step_prefix: String) {
 
   if is_delete_step {
     work_progress.finish_with_message(format!(
-      "Deleted {} files.",
+      "{} {} files.",
+      deleted_word,
       deleted_count.to_string().cyan()
     ));
   } else {
     finish_progress(work_progress, format!(
-      "Copied {} files, {}.",
+      "Copied {} files, {}{}.{}{}{}",
       num_scanned_positive.load(Ordering::SeqCst).to_string().cyan(),
-      bytes_to_string(bytes_to_copy_total.load(Ordering::SeqCst)).cyan()
+      bytes_to_string(bytes_to_copy_total.load(Ordering::SeqCst)).cyan(),
+      written_note(delta_used || sparse_used, bytes_written),
+      metadata_note(metadata_count),
+      hard_link_note(hard_link_count),
+      if linked_count > 0 {
+        format!(" Linked {} unchanged files.", linked_count.to_string().cyan())
+      } else {
+        String::new()
+      }
     ));
   }
   filename_progress.finish_and_clear();
   progress.remove(&filename_progress);
 
-  work_progress = progress.add(ProgressBar::new_spinner());
-  setup_spinner(&mut work_progress, "Finding directories to delete...");
-
-  // Find all directories (and their relative paths) in source
-  let source_dirs: HashSet<PathBuf> = WalkDir::new(&args.source)
-    .into_iter()
-    .filter_map(Result::ok)
-    .filter(|e| e.file_type().is_dir())
-    .map(|e| e.path().strip_prefix(&args.source).unwrap().to_path_buf())
-    .collect();
-  // Find directories in destination that have no relative-path-equivalent in source
-  let mut dst_dirs: Vec<PathBuf> = WalkDir::new(&target)
-    .into_iter()
-    .filter_map(Result::ok)
-    .filter(|e| e.file_type().is_dir())
-    .map(|e| e.path().strip_prefix(&target).unwrap().to_path_buf())
-    .filter(|rel| !source_dirs.contains(rel))
-    .map(|rel| target.join(rel))
-    .collect();
-
-  // Sort to be bottom-up, to prevent "can't delete non-empty dir"
-  dst_dirs.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
-  let dst_dirs_count = dst_dirs.len();
-
-  for dir in dst_dirs {
-    let _ = fs::remove_dir(&dir);
+  // directories only get deleted along with files
+  if !no_delete {
+    work_progress = progress.add(ProgressBar::new_spinner());
+    setup_spinner(&mut work_progress, "Finding directories to delete...");
+
+    // Find all directories (and their relative paths) in source; followed symlinks are synced as directories
+    let source_dirs: HashSet<PathBuf> = WalkDir::new(&args.source)
+      .follow_links(symlinks.follows())
+      .into_iter()
+      .filter_map(Result::ok)
+      .filter(|e| e.file_type().is_dir())
+      .map(|e| e.path().strip_prefix(&args.source).unwrap().to_path_buf())
+      .collect();
+    // Find directories in destination that have no relative-path-equivalent in source
+    let mut dst_dirs: Vec<PathBuf> = WalkDir::new(&dst)
+      .into_iter()
+      .filter_entry(|e| (e.depth() != 1 || e.file_name() != META_DIR) && Some(e.path()) != backup_dir.as_deref())
+      .filter_map(Result::ok)
+      .filter(|e| e.file_type().is_dir())
+      .map(|e| e.path().strip_prefix(&dst).unwrap().to_path_buf())
+      .filter(|rel| !source_dirs.contains(rel))
+      .map(|rel| dst.join(rel))
+      .collect();
+
+    // Sort to be bottom-up, to prevent "can't delete non-empty dir"
+    dst_dirs.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+    let dst_dirs_count = dst_dirs.len();
+
+    for dir in dst_dirs {
+      if args.dry_run {
+        progress_helpers::println(&progress, format!(
+          "{:>10}: {}",
+          "DIR".dimmed().bold(),
+          dir.strip_prefix(&dst).unwrap().display().to_string().dimmed()
+        ));
+      } else {
+        let _ = fs::remove_dir(&dir);
+      }
+    }
+    if dst_dirs_count > 0 {
+      work_progress.finish_with_message(format!(
+        "Deleted {} directories in destination not present in source.",
+        dst_dirs_count.to_string().cyan()
+      ));
+    } else {
+      work_progress.finish_and_clear();
+      progress.remove(&work_progress);
+    }
   }
-  if dst_dirs_count > 0 {
-    work_progress.finish_with_message(format!(
-      "Deleted {} directories in destination not present in source.",
-      dst_dirs_count.to_string().cyan()
-    ));
-  } else {
-    work_progress.finish_and_clear();
-    progress.remove(&work_progress);
+  // directories are only complete now: their metadata would change while their contents are synced
+  if !args.dry_run {
+    let failed = WalkDir::new(&args.source)
+      .follow_links(symlinks.follows())
+      .into_iter()
+      .filter_map(Result::ok)
+      .filter(|e| e.file_type().is_dir())
+      .map(|e| (e.path().to_path_buf(), dst.join(e.path().strip_prefix(&args.source).unwrap())))
+      .filter(|(_, to)| to.is_dir())
+      .filter(|(from, to)| metadata::apply_to_dir(from, to, preserve).is_err())
+      .count();
+    if failed > 0 {
+      println!("{}", format!("Failed to preserve metadata of {} directories.", failed).bright_red());
+    }
+  }
+  if snapshot && !args.dry_run
+    && let Err(err) = fs::create_dir_all(&dst).and_then(|_| snapshot::update_pointers(&target, &dst)) {
+    println!("{}", format!("Failed to update snapshot pointers: {}", err).bright_red());
+  }
+  if snapshot && args.retention.is_set() {
+    prune::prune(&target, &args.retention, args.dry_run);
+  }
+  if let Some(index) = index && !args.dry_run
+    && let Err(err) = index.lock().unwrap().save(&target) {
+    println!("{}", format!("Failed to write index: {}", err).bright_red());
+  }
+  // restores don't write anything but the restored files into the source
+  if !args.dry_run && args.protected_rules.is_none()
+    && let Err(err) = metadata::save_skipped_xattrs(&target, &skipped_xattrs) {
+    println!("{}", format!("Failed to write skipped extended attributes: {}", err).bright_red());
   }
   if args.no_delete {
     println!("{}", " Delete step was skipped! ".on_yellow().bold());
   }
-}
\ No newline at end of file
+  if args.dry_run {
+    println!("{}", " Dry run: target was not modified! ".on_yellow().bold());
+  }
+  if num_verify_failed > 0 {
+    println!("{}", format!(" {} files failed verification! ", num_verify_failed).on_red().bold());
+  }
+  num_verify_failed
+}
+
+/// Notes how many bytes were actually written, if delta updates or holes of sparse files made that differ from the bytes copied.
+fn written_note(partially_written: bool, bytes_written: u64) -> String {
+  if partially_written {
+    format!(" ({} written)", bytes_to_string(bytes_written).cyan())
+  } else {
+    String::new()
+  }
+}
+
+/// Notes how many unchanged files only had their metadata updated, if any.
+fn metadata_note(metadata_count: usize) -> String {
+  if metadata_count > 0 {
+    format!(" Updated metadata of {} files.", metadata_count.to_string().cyan())
+  } else {
+    String::new()
+  }
+}
+
+/// Notes how many hard links between source files were recreated, if any.
+fn hard_link_note(hard_link_count: usize) -> String {
+  if hard_link_count > 0 {
+    format!(" Recreated {} hard links.", hard_link_count.to_string().cyan())
+  } else {
+    String::new()
+  }
+}
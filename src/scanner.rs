@@ -1,13 +1,89 @@
-use std::{fs, path::{Component, Path, PathBuf}, sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc}};
+use std::{collections::{HashMap, HashSet}, fs::{self, Metadata}, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc, Mutex}, thread};
 
 use colored::Colorize;
-use crossbeam::channel::Sender;
-use glob::Pattern;
+use crossbeam::channel::{bounded, Sender};
 use indicatif::ProgressBar;
-use walkdir::WalkDir;
+use walkdir::{DirEntry, WalkDir};
 
-use crate::{progress_helpers::{spinner_style, PROGRESS_SPINNER_TICKRATE}, task_copy_delete, Task};
+use crate::{
+  compare::{compare_content, compare_metadata, Comparison, FileState},
+  config::{compare_mode::CompareMode, preserve::Preserve, symlink_mode::SymlinkMode},
+  ignore_files::IgnoreFiles,
+  index::{Index, IndexEntry, META_DIR},
+  metadata,
+  progress_helpers::{spinner_style, PROGRESS_SPINNER_TICKRATE},
+  filter::Filter,
+  task_copy_delete::{self, is_resumable, is_temp_file}, Task, CHANNEL_CAPACITY
+};
 
+/// Upper limit of threads hashing files in parallel; more threads just fight over disk access.
+const HASH_THREADS_MAX: usize = 4;
+
+/// Returns true if a walked entry is a directory the filter excludes as a whole, which the walk can skip.
+pub fn is_excluded_dir(entry: &DirEntry, src: &Path, filter: &Filter) -> bool {
+  entry.depth() > 0 && entry.file_type().is_dir() && filter.excludes_dir(entry.path().strip_prefix(src).unwrap())
+}
+
+/// Identifies a file with more than one hard link: device and inode number. Always None on other platforms than Unix.
+fn hard_link_key(metadata: &Metadata) -> Option<(u64, u64)> {
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.is_file() && metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+  }
+  #[cfg(not(unix))]
+  {
+    let _ = metadata;
+    None
+  }
+}
+
+/// Returns true if a directory (relative to the target) is a symlink in the target, or is below one.
+/// Results are cached by directory, so each one is only looked at once.
+fn is_linked_dir(dir: &Path, target: &Path, cache: &mut HashMap<PathBuf, bool>) -> bool {
+  if dir.as_os_str().is_empty() { return false; }
+  if let Some(&linked) = cache.get(dir) { return linked; }
+  let linked = is_linked_dir(dir.parent().unwrap_or(Path::new("")), target, cache)
+    || fs::symlink_metadata(target.join(dir)).is_ok_and(|m| m.is_symlink());
+  cache.insert(dir.to_path_buf(), linked);
+  linked
+}
+
+/// Walks the source like a sync does, yielding everything but directories: ignored and excluded directories are
+/// pruned, and symlinks are walked, followed or skipped according to the symlink mode. Errors are yielded as well;
+/// syncs skip what can't be read. `on_pruned` is called with the ignored and excluded entries skipped,
+/// `on_refused` with symlinks "follow-safe" doesn't follow, because they point outside of the source.
+pub fn walk_source<'a>(
+  src: &'a Path,
+  filter: &'a Filter,
+  symlinks: SymlinkMode,
+  ignore_files: &'a mut IgnoreFiles,
+  mut on_pruned: impl FnMut(&DirEntry) + 'a,
+  mut on_refused: impl FnMut(&Path) + 'a
+) -> impl Iterator<Item = walkdir::Result<DirEntry>> + 'a {
+  let safe_root = (symlinks == SymlinkMode::FollowSafe).then(|| fs::canonicalize(src).unwrap_or_else(|_| src.to_path_buf()));
+  WalkDir::new(src).follow_links(symlinks.follows()).into_iter()
+    .filter_entry(move |e| {
+      if ignore_files.is_ignored(e) || is_excluded_dir(e, src, filter) {
+        on_pruned(e);
+        return false;
+      }
+      if e.depth() == 0 || !e.path_is_symlink() { return true; }
+      match symlinks {
+        SymlinkMode::Skip => false,
+        SymlinkMode::FollowSafe => {
+          // broken links can't be resolved, so they're refused as well
+          let inside = fs::canonicalize(e.path()).is_ok_and(|target| safe_root.as_ref().is_some_and(|root| target.starts_with(root)));
+          if !inside { on_refused(e.path()); }
+          inside
+        }
+        _ => true,
+      }
+    })
+    .filter(|e| !e.as_ref().is_ok_and(|e| e.file_type().is_dir()))
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn scanner(
   src: PathBuf,
   dst: PathBuf,
@@ -16,150 +92,228 @@ pub fn scanner(
   num_delete: Arc<AtomicUsize>,
   bytes_to_copy: Arc<AtomicU64>,
   progress: &ProgressBar,
-  exclude_dirs: Vec<String>,
-  exclude_files: Vec<String>,
-  exclude_patterns: Vec<String>,
-  include_dirs: Vec<String>,
-  include_files: Vec<String>,
-  include_patterns: Vec<String>,
-  force_include_dirs: Vec<String>,
-  force_include_files: Vec<String>,
-  force_include_patterns: Vec<String>,
+  filter: Filter,
+  protected: Option<(Filter, PathBuf)>,
+  compare: CompareMode,
+  link_dest: Option<PathBuf>,
+  backup_dir: Option<PathBuf>,
+  previous_index: Option<Index>,
+  index: Option<Arc<Mutex<Index>>>,
+  interrupted: HashSet<String>,
+  skipped_xattrs: HashSet<String>,
+  symlinks: SymlinkMode,
+  preserve: Preserve,
   no_delete: bool
 ) {
   let mut scanned_total: u64 = 0;
-  let exclude_patterns_parsed: Vec<Pattern> = exclude_patterns
-    .iter().map(|p| Pattern::new(p))
-    .map(|p| match p {
-      Ok(pt) => pt,
-      Err(err) => panic!("Error in pattern: {}", err.msg)
-    })
-    .collect();
-  let include_patterns_parsed: Vec<Pattern> = include_patterns
-    .iter().map(|p| Pattern::new(p))
-    .map(|p| match p {
-      Ok(pt) => pt,
-      Err(err) => panic!("Error in pattern: {}", err.msg)
-    })
-    .collect();
-  let force_include_patterns_parsed: Vec<Pattern> = force_include_patterns
-    .iter().map(|p| Pattern::new(p))
-    .map(|p| match p {
-      Ok(pt) => pt,
-      Err(err) => panic!("Error in pattern: {}", err.msg)
-    })
-    .collect();
+  // Files are compared against the link destination (previous snapshot) if given, otherwise against the destination.
+  // If the index is used, the last run's index replaces reading metadata (and hashes) from there.
+  let reference = link_dest.as_ref().unwrap_or(&dst);
+  // Helper function: counts a copy task (for worker progress) and sends it
+  let send_copy = |task: task_copy_delete::Copy| {
+    num_positive.fetch_add(1, Ordering::SeqCst);
+    bytes_to_copy.fetch_add(task.bytes, Ordering::SeqCst);
+    tx.send(Task::Copy(task)).unwrap();
+  };
+  // Helper function: records an unchanged file in the new index and, if linking, sends a link task instead of the copy.
+  // If only its preserved metadata changed, that's updated instead.
+  let keep_unchanged = |task: task_copy_delete::Copy, path_in_reference: PathBuf, entry: IndexEntry, metadata_changed: bool| {
+    if metadata_changed {
+      if link_dest.is_some() {
+        // hard links share their metadata with the previous snapshot, which must not change
+        send_copy(task);
+      } else {
+        tx.send(Task::UpdateMetadata(task_copy_delete::UpdateMetadata::new(
+          task.from,
+          task.to,
+          task.relative,
+          preserve,
+          entry.hash()
+        ))).unwrap();
+      }
+      return;
+    }
+    if let Some(index) = &index {
+      index.lock().unwrap().insert(task.relative.clone(), entry);
+    }
+    if link_dest.is_some() {
+      tx.send(Task::Link(task_copy_delete::Link::new(
+        path_in_reference,
+        task.to,
+        task.relative,
+        task.bytes,
+        preserve
+      ))).unwrap();
+    }
+  };
+
+  // Files hard-linked in the source: the destination of the first included link of each file,
+  // and link tasks for the others. Those are sent after all copies, when the first link's copy is done.
+  let mut hard_links: HashMap<(u64, u64), PathBuf> = HashMap::new();
+  let mut hard_link_tasks = Vec::new();
 
-  for entry in WalkDir::new(&src).into_iter().filter_map(Result::ok) {
-    if entry.file_type().is_dir() { continue; }
-    let relative_path = entry.path().strip_prefix(&src).unwrap();
-    let relative_path_parentdirs = relative_path.parent().unwrap_or_else(|| Path::new(""));
-    let path_in_dst = dst.join(relative_path);
+  thread::scope(|scope| {
+    // Hasher threads: compare content of files whose metadata doesn't tell if they changed.
+    // Only used in checksum mode; finished before the scope ends, so deletes are still sent last.
+    let (hash_tx, hash_rx) = bounded::<(task_copy_delete::Copy, PathBuf, IndexEntry, bool)>(CHANNEL_CAPACITY);
+    let hash_threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(HASH_THREADS_MAX);
+    for _ in 0..hash_threads {
+      let hash_rx = hash_rx.clone();
+      let send_copy = &send_copy;
+      let keep_unchanged = &keep_unchanged;
+      scope.spawn(move || {
+        for (task, path_in_reference, mut dst_entry, metadata_changed) in hash_rx {
+          match compare_content(&task.from, &path_in_reference, dst_entry.hash()) {
+            (Comparison::Equal, hash) => {
+              dst_entry.hash = hash.map(|h| h.to_hex().to_string());
+              keep_unchanged(task, path_in_reference, dst_entry, metadata_changed);
+            }
+            _ => send_copy(task),
+          }
+        }
+      });
+    }
+
+    // ignored and excluded directories are pruned from the walk; errors in ignore files were reported when counting
+    let mut ignore_files = filter.ignore_files();
+    // a directory of the source may be a symlink in the target (eg. if it was one in the source before): files below
+    // it are treated as missing, and the link is replaced by a directory when copying them
+    let mut linked_dirs = HashMap::new();
+    let on_refused = |path: &Path| progress.println(format!("{}", format!(
+      "Symlink points outside of the source, skipped: {}",
+      path.display()
+    ).yellow()));
+    for entry in walk_source(&src, &filter, symlinks, &mut ignore_files, |_| {}, on_refused).filter_map(Result::ok) {
+      let relative_path = entry.path().strip_prefix(&src).unwrap();
+      let relative = relative_path.display().to_string();
+      let path_in_dst = dst.join(relative_path);
+      let path_in_reference = reference.join(relative_path);
+      let in_linked_dir = is_linked_dir(relative_path.parent().unwrap_or(Path::new("")), reference, &mut linked_dirs);
     
-    // check exclusions
-    let excluded: bool = 
-      // dir name - exact
-      relative_path_parentdirs.components().any(|c| match c {
-        Component::Normal(os) => 
-          exclude_dirs.iter().any(|ex| ex == &os.to_string_lossy()),
-        _ => false
-      })
-      || // file name - exact
-      entry.file_name()
-        .to_str()
-        .map(|s| exclude_files.iter().any(|ex| ex == s))
-        .unwrap_or(false)
-      || // pattern match
-      exclude_patterns_parsed.iter().any(|pattern| pattern.matches_path(relative_path));
-
-    // check inclusions
-    let included: bool = !excluded && (
-        // no dir rules at all or any dir rule matches
-        include_dirs.len() == 0 || relative_path_parentdirs.components().any(|c| match c {
-          Component::Normal(os) =>
-            include_dirs.iter().any(|inc| inc  == &os.to_string_lossy()),
-          _ => false
-      })) && (
-        // no file rules at all or entry is not a file or any file rule matches
-        include_files.len() == 0 || 
-        entry.file_name()
-          .to_str()
-          .map(|s| include_files.iter().any(|inc| inc == s))
-          .unwrap_or(false)
-      ) && (
-        // no pattern rules at all or any pattern matches
-        include_patterns.len() == 0 || include_patterns_parsed.iter().any(|pt| pt.matches_path(relative_path))
-      );
+      let src_metadata = entry.metadata().unwrap();
+      let bytes = src_metadata.len();
 
-    // check forced inclusions
-    let force_included: bool =
-      // dir name
-      relative_path_parentdirs.components().any(|c| match c {
-        Component::Normal(os) => 
-          force_include_dirs.iter().any(|ex| ex == &os.to_string_lossy()),
-        _ => false
-      })
-      || // file name
-      entry.file_name()
-        .to_str()
-        .map(|s| force_include_files.iter().any(|ex| ex == s))
-        .unwrap_or(false)
-      || // pattern match
-      force_include_patterns_parsed.iter().any(|pattern| pattern.matches_path(relative_path));
-
-    let src_metadata = entry.metadata().unwrap();
-    let bytes = src_metadata.len();
-
-    let needs_copy = 
-      if (excluded || !included) && !force_included {
-        false
-      } else {
-        match fs::metadata(&path_in_dst) {
-          Ok(metadata) => {
-            let src_mtime = src_metadata.modified().unwrap();
-            let dst_mtime = metadata.modified().unwrap();
-            
-            src_mtime > dst_mtime
-            || bytes != metadata.len()
+      // only walked without following them: symlinks are recreated, unless the target already has the same link
+      if entry.file_type().is_symlink() {
+        if filter.is_included(relative_path, Some(&src_metadata))
+          && let Ok(link_target) = fs::read_link(entry.path()) {
+          let unchanged = !in_linked_dir && fs::read_link(&path_in_reference).is_ok_and(|existing| existing == link_target);
+          if !unchanged {
+            num_positive.fetch_add(1, Ordering::SeqCst);
+          }
+          // snapshots always need their own link
+          if !unchanged || link_dest.is_some() {
+            tx.send(Task::Symlink(task_copy_delete::Symlink::new(
+              entry.path().to_path_buf(),
+              link_target,
+              path_in_dst,
+              relative
+            ))).unwrap();
           }
-          Err(_) => true // file missing in destination, copy
         }
-      };
+        progress.inc(1);
+        scanned_total += 1;
+        continue;
+      }
+
+      let included = filter.is_included(relative_path, Some(&src_metadata));
+      if included && let Some(key) = hard_link_key(&src_metadata) {
+        if let Some(first) = hard_links.get(&key) {
+          hard_link_tasks.push(task_copy_delete::HardLink::new(first.clone(), path_in_dst, relative));
+          progress.inc(1);
+          scanned_total += 1;
+          continue;
+        }
+        hard_links.insert(key, path_in_dst.clone());
+      }
 
-    if needs_copy {
-      // increment positive match count (for worker progress) and send task
-      num_positive.fetch_add(1, Ordering::SeqCst);
-      bytes_to_copy.fetch_add(bytes, Ordering::SeqCst);
-      tx.send(Task::Copy(task_copy_delete::Copy::new(
+      let comparison = 
+        if !included {
+          None
+        } else {
+          // destination state: from the index if it has an entry, otherwise from the file system
+          // files below a symlinked directory are missing in the destination
+          let dst_entry = (!in_linked_dir).then(|| previous_index.as_ref()
+            .and_then(|index| index.get(&relative).cloned())
+            // a symlink in the destination is replaced like a missing file
+            .or_else(|| fs::symlink_metadata(&path_in_reference).ok()
+              .filter(|m| !m.is_symlink())
+              .map(|m| IndexEntry::from_metadata(&m, None)))
+          ).flatten();
+          Some(match dst_entry {
+            // partially updated by an interrupted delta update
+            Some(dst_entry) if interrupted.contains(&relative) => (Comparison::Different, Some(dst_entry)),
+            Some(dst_entry) => (
+              compare_metadata(compare, &FileState::from(&src_metadata), &dst_entry.state()),
+              Some(dst_entry)
+            ),
+            None => (Comparison::Different, None) // file missing in destination, copy
+          })
+        };
+
+      let task = || task_copy_delete::Copy::new(
         entry.path().to_path_buf(),
-        path_in_dst,
-        relative_path.display().to_string(),
-        bytes
-      ))).unwrap();
-    }
+        path_in_dst.clone(),
+        relative.clone(),
+        bytes,
+        preserve
+      );
+      let metadata_changed = |dst_entry: &IndexEntry|
+        metadata::differs(&src_metadata, entry.path(), dst_entry, &path_in_reference, preserve, &skipped_xattrs);
+      match comparison {
+        Some((Comparison::Different, _)) => send_copy(task()),
+        Some((Comparison::Ambiguous, Some(dst_entry))) => {
+          let metadata_changed = metadata_changed(&dst_entry);
+          hash_tx.send((task(), path_in_reference, dst_entry, metadata_changed)).unwrap()
+        }
+        Some((Comparison::Equal, Some(dst_entry))) => {
+          let metadata_changed = metadata_changed(&dst_entry);
+          keep_unchanged(task(), path_in_reference, dst_entry, metadata_changed)
+        }
+        _ => {}
+      }
 
-    progress.inc(1);
-    scanned_total += 1;
+      progress.inc(1);
+      scanned_total += 1;
+    }
+    // lets hasher threads finish once all queued files are hashed
+    drop(hash_tx);
+  });
+  for task in hard_link_tasks {
+    tx.send(Task::HardLink(task)).unwrap();
   }
 
   progress.set_style(spinner_style());
-  if !no_delete {
-    // replace progress bar with spinner
-    progress.enable_steady_tick(PROGRESS_SPINNER_TICKRATE);
-    progress.set_message("Finding files to delete...");
-
-    // find files to delete
-    for entry in WalkDir::new(&dst).into_iter().filter_map(Result::ok) {
-      if entry.file_type().is_file() {
-        let relative_path = entry.path().strip_prefix(&dst).unwrap();
-        let path_in_src = src.join(relative_path);
-        if !path_in_src.exists() {
-          num_delete.fetch_add(1, Ordering::SeqCst);
-          tx.send(Task::Delete(task_copy_delete::Delete::new(
-            entry.path().to_path_buf(),
-            relative_path.display().to_string()
-          ))).unwrap();
-        }
+  // replace progress bar with spinner
+  progress.enable_steady_tick(PROGRESS_SPINNER_TICKRATE);
+  progress.set_message(if no_delete { "Cleaning up..." } else { "Finding files to delete..." });
+
+  // Helper function: true for files a restore must not delete, as the restored operation's rules didn't include them
+  let is_protected = |relative_path: &Path, entry: &DirEntry| protected.as_ref().is_some_and(|(filter, base)|
+    !filter.is_included(&base.join(relative_path), entry.metadata().ok().as_ref())
+  );
+  // find files to delete and temporary files left behind by interrupted runs
+  for entry in WalkDir::new(&dst).into_iter()
+    .filter_entry(|e| (e.depth() != 1 || e.file_name() != META_DIR) && Some(e.path()) != backup_dir.as_deref())
+    .filter_map(Result::ok) {
+    if entry.file_type().is_file() || entry.file_type().is_symlink() {
+      let relative_path = entry.path().strip_prefix(&dst).unwrap();
+      let path_in_src = src.join(relative_path);
+      // broken symlinks in the source still exist
+      if fs::symlink_metadata(&path_in_src).is_ok() { continue; }
+      if is_temp_file(entry.file_name()) {
+        // never part of the backup: removed, but not counted or moved to the backup dir - unless it can be resumed
+        if is_resumable(entry.path(), path_in_src.parent().unwrap_or(&src)) { continue; }
+        tx.send(Task::Cleanup(task_copy_delete::Cleanup::new(
+          entry.path().to_path_buf(),
+          relative_path.display().to_string()
+        ))).unwrap();
+      } else if !no_delete && !is_protected(relative_path, &entry) {
+        num_delete.fetch_add(1, Ordering::SeqCst);
+        tx.send(Task::Delete(task_copy_delete::Delete::new(
+          entry.path().to_path_buf(),
+          relative_path.display().to_string()
+        ))).unwrap();
       }
     }
   }
@@ -0,0 +1,59 @@
+use std::{fs, io, path::{Path, PathBuf}};
+
+use chrono::NaiveDateTime;
+
+use crate::{index::META_DIR, util::timestamp::{timestamp, TIMESTAMP_FORMAT}};
+
+/// Name of the pointer to the latest snapshot, in the target.
+pub const LATEST: &str = "latest";
+/// Name of the listing of all snapshots, in the target's meta directory.
+const LISTING_FILE: &str = "snapshots";
+
+/// Parses the creation time from a snapshot directory name.
+pub fn parse_snapshot_name(name: &str) -> Option<NaiveDateTime> {
+  NaiveDateTime::parse_from_str(name, TIMESTAMP_FORMAT).ok()
+}
+
+/// Returns the names of all snapshot directories in the target, oldest first.
+pub fn list_snapshots(target: &Path) -> Vec<String> {
+  let mut snapshots: Vec<String> = fs::read_dir(target)
+    .map(|entries| entries
+      .filter_map(Result::ok)
+      .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+      .filter_map(|e| e.file_name().to_str().map(String::from))
+      .filter(|name| parse_snapshot_name(name).is_some())
+      .collect())
+    .unwrap_or_default();
+  snapshots.sort();
+  snapshots
+}
+
+/// Returns the directory of the newest snapshot in the target, if there is one.
+pub fn latest_snapshot(target: &Path) -> Option<PathBuf> {
+  list_snapshots(target).pop().map(|name| target.join(name))
+}
+
+/// Returns the directory for a new snapshot, named after the current time.
+pub fn new_snapshot(target: &Path) -> PathBuf {
+  target.join(timestamp())
+}
+
+/// Points "latest" to the given snapshot and rewrites the listing of snapshots.
+/// On Unix, "latest" is a symlink; elsewhere, it is a text file containing the snapshot name.
+pub fn update_pointers(target: &Path, snapshot: &Path) -> io::Result<()> {
+  let name = snapshot.file_name().unwrap();
+  let latest = target.join(LATEST);
+  if fs::symlink_metadata(&latest).is_ok() {
+    fs::remove_file(&latest)?;
+  }
+  #[cfg(unix)]
+  std::os::unix::fs::symlink(name, &latest)?;
+  #[cfg(not(unix))]
+  fs::write(&latest, name.to_string_lossy().as_bytes())?;
+
+  let listing = target.join(META_DIR).join(LISTING_FILE);
+  fs::create_dir_all(listing.parent().unwrap())?;
+  let mut names = list_snapshots(target).join("\n");
+  names.push('\n');
+  fs::write(listing, names)
+}
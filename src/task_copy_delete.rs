@@ -1,49 +1,282 @@
-use std::{fs, io::{Read, Write}, path::PathBuf};
+use std::{ffi::{OsStr, OsString}, fs::{self, Metadata}, io::{self, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}};
 
 use filetime::FileTime;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
 
-use crate::progress_helpers::PROGERSS_BAR_FILE;
+use crate::{config::preserve::Preserve, delta, metadata, progress_helpers::PROGERSS_BAR_FILE, util::{bytes_to_string::bytes_to_string, drop_cache::drop_cache, hash_file::{hash_file, hash_file_prefix}, move_file::move_file, sparse}};
+
+/// Prefix of the temporary files copies are written to, before being renamed over their destination.
+pub const TEMP_PREFIX: &str = ".baccy-tmp-";
+
+/// Returns true if a file name belongs to a temporary file of a (possibly interrupted) copy.
+pub fn is_temp_file(name: &OsStr) -> bool {
+  name.to_string_lossy().starts_with(TEMP_PREFIX)
+}
+
+/// Suffix of the sidecar file next to a large copy's temporary file, making the copy resumable.
+const RESUME_SUFFIX: &str = ".resume";
+
+/// Content of a resume sidecar: the state of the source file the temporary file was copied from.
+#[derive(PartialEq, Serialize, Deserialize)]
+struct ResumeInfo {
+  bytes: u64,
+  mtime: i64,
+  mtime_nanos: u32,
+}
+
+impl ResumeInfo {
+  fn from_metadata(metadata: &Metadata) -> Self {
+    let mtime = FileTime::from_last_modification_time(metadata);
+    Self {
+      bytes: metadata.len(),
+      mtime: mtime.unix_seconds(),
+      mtime_nanos: mtime.nanoseconds(),
+    }
+  }
+
+  fn read(sidecar: &Path) -> Option<Self> {
+    serde_json::from_str(&fs::read_to_string(sidecar).ok()?).ok()
+  }
+}
+
+fn sidecar_path(temp: &Path) -> PathBuf {
+  let mut path = temp.as_os_str().to_owned();
+  path.push(RESUME_SUFFIX);
+  PathBuf::from(path)
+}
+
+/// Returns true if a temporary file (or its sidecar) belongs to an interrupted copy that can be resumed,
+/// i.e. its sidecar still matches the source file. `src_dir` is the source directory corresponding to its parent.
+pub fn is_resumable(path: &Path, src_dir: &Path) -> bool {
+  let name = path.file_name().unwrap_or_default().to_string_lossy();
+  let temp_name = name.strip_suffix(RESUME_SUFFIX).unwrap_or(&name);
+  let Some(file_name) = temp_name.strip_prefix(TEMP_PREFIX) else { return false };
+  let Ok(src_metadata) = fs::metadata(src_dir.join(file_name)) else { return false };
+  ResumeInfo::read(&path.with_file_name(format!("{temp_name}{RESUME_SUFFIX}")))
+    .is_some_and(|info| info == ResumeInfo::from_metadata(&src_metadata))
+}
+
+/// Temporary sibling of a destination, named with `TEMP_PREFIX`.
+fn temp_path(to: &Path) -> PathBuf {
+  let mut name = OsString::from(TEMP_PREFIX);
+  name.push(to.file_name().unwrap_or_default());
+  to.with_file_name(name)
+}
+
+/// Returns the parent directories of a destination below the target, outermost first; `relative` is its path
+/// relative to the target.
+fn parent_directories(to: &Path, relative: &str) -> Vec<PathBuf> {
+  let relative = Path::new(relative);
+  let Some(target) = to.ancestors().nth(relative.components().count()) else { return Vec::new() };
+  relative.parent().into_iter().flat_map(Path::components)
+    .scan(target.to_path_buf(), |dir, component| {
+      dir.push(component);
+      Some(dir.clone())
+    })
+    .collect()
+}
+
+fn is_symlink(path: &Path) -> bool {
+  fs::symlink_metadata(path).is_ok_and(|meta| meta.is_symlink())
+}
+
+/// Creates the parent directories of a destination. Symlinks on the way below the target (eg. where the source
+/// had a link to a directory before) are replaced by directories: files would be written through them, to wherever
+/// they point.
+fn create_parent_directories(to: &Path, relative: &str) -> io::Result<()> {
+  for dir in parent_directories(to, relative) {
+    if is_symlink(&dir) {
+      // links to directories are directories themselves on Windows
+      fs::remove_file(&dir).or_else(|_| fs::remove_dir(&dir))?;
+    }
+  }
+  match to.parent() {
+    Some(parent) => fs::create_dir_all(parent),
+    None => Ok(()),
+  }
+}
+
+/// Reader feeding everything read through it into a hasher.
+struct HashingReader<'a, R: Read> {
+  inner: R,
+  hasher: &'a mut blake3::Hasher,
+}
+
+impl<R: Read> Read for HashingReader<'_, R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let num_bytes = self.inner.read(buf)?;
+    self.hasher.update(&buf[..num_bytes]);
+    Ok(num_bytes)
+  }
+}
+
+/// Returns true if a file has more than one hard link. Always false on other platforms than Unix.
+fn has_other_links(metadata: &Metadata) -> bool {
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink() > 1
+  }
+  #[cfg(not(unix))]
+  {
+    let _ = metadata;
+    false
+  }
+}
+
+/// Zeros fed into hashers for the holes of sparse files.
+static ZEROS: [u8; 64 * 1024] = [0; 64 * 1024];
+
+fn hash_zeros(hasher: Option<&mut blake3::Hasher>, mut len: u64) {
+  let Some(hasher) = hasher else { return };
+  while len > 0 {
+    let num_bytes = len.min(ZEROS.len() as u64) as usize;
+    hasher.update(&ZEROS[..num_bytes]);
+    len -= num_bytes as u64;
+  }
+}
+
+/// Result of a copy: the number of bytes written, and whether holes of a sparse source were skipped.
+/// Less bytes than the file's size are written for holes, but also when resuming an interrupted copy.
+#[derive(Debug, Default)]
+pub struct Written {
+  pub bytes: u64,
+  pub skipped_holes: bool,
+  /// Error preserving the metadata of the copy; the copy itself is complete.
+  pub metadata_error: Option<io::Error>,
+}
+
+/// Copies a file from `offset` on into the writer, at the same offset. Of a sparse source, only the regions holding data
+/// (up to `len`) are written, so its holes stay holes in the destination; others are copied to their end.
+/// The hasher is fed with the whole content, holes as zeros. Reports the copied and written bytes after each chunk.
+fn copy_sparse(
+  reader: &mut fs::File,
+  writer: &mut fs::File,
+  offset: u64,
+  len: u64,
+  sparse: bool,
+  mut hasher: Option<&mut blake3::Hasher>,
+  mut on_progress: impl FnMut(u64, u64)
+) -> io::Result<Written> {
+  let regions = if sparse { sparse::data_regions(reader, offset, len)? } else { vec![(offset, u64::MAX)] };
+  // 1MiB buffer is too big for stack (1MiB total stack size...)
+  // let mut buffer = [0u8; 1024*1024];
+  // 4MiB vector-buffer lives on heap, better performance overall
+  let mut buffer = vec![0u8; 1024 * 1024 * 4];
+  let mut copied = offset;
+  let mut written: u64 = 0;
+  let mut skipped_holes = false;
+
+  for (start, end) in regions {
+    if start > copied {
+      skipped_holes = true;
+      hash_zeros(hasher.as_deref_mut(), start - copied);
+      copied = start;
+      on_progress(copied, written);
+    }
+    reader.seek(SeekFrom::Start(start))?;
+    writer.seek(SeekFrom::Start(start))?;
+    let mut region = (&mut *reader).take(end - start);
+    loop {
+      let num_bytes = region.read(&mut buffer)?;
+      if num_bytes == 0 {break;}
+      writer.write_all(&buffer[..num_bytes])?;
+      if let Some(hasher) = hasher.as_deref_mut() {
+        hasher.update(&buffer[..num_bytes]);
+      }
+      copied += num_bytes as u64;
+      written += num_bytes as u64;
+      on_progress(copied, written);
+    }
+  }
+  // a hole at the end: only extends the file
+  if sparse && copied < len {
+    skipped_holes = true;
+    hash_zeros(hasher, len - copied);
+    writer.set_len(len)?;
+    on_progress(len, written);
+  }
+  Ok(Written{bytes: written, skipped_holes, metadata_error: None})
+}
 
 pub struct Copy {
   pub from: PathBuf,
   pub to: PathBuf,
   pub relative: String,
   pub bytes: u64,
+  pub preserve: Preserve,
 }
 
 impl Copy {
-  pub fn new(from: PathBuf, to: PathBuf, relative: String, bytes: u64) -> Self {
-    Self{from, to, relative, bytes}
+  pub fn new(from: PathBuf, to: PathBuf, relative: String, bytes: u64, preserve: Preserve) -> Self {
+    Self{from, to, relative, bytes, preserve}
   }
 
-  fn copy_mtime(&self) {
-    if let Ok(meta) = fs::metadata(&self.from) {
-      if let Ok(mtime) = meta.modified() {
-        let _ = filetime::set_file_mtime(&self.to, FileTime::from_system_time(mtime));
-      }
-    }
+  fn create_parent_directories(&self) {
+    create_parent_directories(&self.to, &self.relative)
+      .unwrap_or_else(|_| panic!("Failed to create directories for {}", self.to.display()))
   }
 
-  fn create_parent_directories(&self) {
-    if let Some(parent) = self.to.parent() {
-      fs::create_dir_all(parent)
-        .expect(format!("Failed to create directories for {}", self.to.display()).as_str())
+  /// Temporary sibling of the destination. Copies are written here first, so an interrupted copy
+  /// never leaves a truncated file at the destination.
+  fn temp_path(&self) -> PathBuf {
+    temp_path(&self.to)
+  }
+
+  /// Flushes the written temporary file to disk, copies the preserved metadata (as read before copying,
+  /// reading changes the access time), then renames it over the destination.
+  /// Returns the error preserving metadata, if any; it doesn't fail the copy.
+  fn commit(&self, writer: fs::File, temp: &Path, src_metadata: &Metadata) -> io::Result<Option<io::Error>> {
+    writer.sync_all()?;
+    drop(writer);
+    let metadata_error = metadata::apply(&self.from, src_metadata, temp, self.preserve).err();
+    fs::rename(temp, &self.to)?;
+    Ok(metadata_error)
+  }
+
+  /// Moves an existing destination file (or symlink) into the trash directory, before it gets overwritten.
+  pub fn move_existing_to_trash(&self, trash: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(&self.to).is_ok() {
+      move_file(&self.to, &trash.join(&self.relative))
+    } else {
+      Ok(())
     }
   }
 
-  pub fn execute(&self) -> std::io::Result<()> {
+  /// Copies the file, keeping the holes of sparse files. If a hasher is given, it's fed with the source's content
+  /// while copying.
+  pub fn execute(&self, hasher: Option<&mut blake3::Hasher>) -> std::io::Result<Written> {
     self.create_parent_directories();
-    let res = fs::copy(&self.from, &self.to).map(|_| ());
-    self.copy_mtime();
+    let temp = self.temp_path();
+    let res = fs::File::open(&self.from).and_then(|mut reader| {
+      let src_metadata = reader.metadata()?;
+      let mut writer = fs::File::create(&temp)?;
+      let written = if sparse::is_sparse(&src_metadata) {
+        copy_sparse(&mut reader, &mut writer, 0, src_metadata.len(), true, hasher, |_, _| {})?
+      } else {
+        let bytes = match hasher {
+          Some(hasher) => io::copy(&mut HashingReader{inner: reader, hasher}, &mut writer)?,
+          None => io::copy(&mut reader, &mut writer)?,
+        };
+        Written{bytes, ..Default::default()}
+      };
+      let metadata_error = self.commit(writer, &temp, &src_metadata)?;
+      Ok(Written{metadata_error, ..written})
+    });
+    if res.is_err() {
+      let _ = fs::remove_file(&temp);
+    }
     res
   }
 
-  pub fn execute_with_progress(&self, progress: &MultiProgress, worker_progress: &ProgressBar) -> std::io::Result<()> {
+  /// Copies the file, showing its own progress bar, resuming interrupted copies and keeping the holes of sparse files.
+  /// If a hasher is given, it's fed with the source's content while copying.
+  pub fn execute_with_progress(&self, progress: &MultiProgress, worker_progress: &ProgressBar, hasher: Option<&mut blake3::Hasher>) -> std::io::Result<Written> {
     let file_progress = progress.add(ProgressBar::new(self.bytes));
     file_progress.set_style(
       // ProgressStyle::with_template("Copying: {msg} {wide_bar} {bytes} / {total_bytes} ({bytes_per_sec})")
-      ProgressStyle::with_template("Copying: {wide_bar} {bytes} / {total_bytes}")
+      ProgressStyle::with_template("{msg}: {wide_bar} {bytes} / {total_bytes}")
       .unwrap()
       .progress_chars(PROGERSS_BAR_FILE)
     );
@@ -52,32 +285,267 @@ impl Copy {
     // ));
     
     self.create_parent_directories();
+    // on failure, the temporary file is kept: the next run resumes the copy
+    let res = self.copy_with_progress(&self.temp_path(), &file_progress, worker_progress, hasher);
+
+    file_progress.finish_and_clear();
+    progress.remove(&file_progress);
+    res
+  }
+
+  /// Returns true if the destination is a regular file, which delta updates can write into.
+  /// A symlink (eg. left by an earlier sync), also in place of a parent directory, would have the update written
+  /// through it, to wherever it points; a file with other hard links (eg. recreated from the source) would change
+  /// along with them.
+  pub fn can_update_in_place(&self) -> bool {
+    fs::symlink_metadata(&self.to).is_ok_and(|meta| meta.file_type().is_file() && !has_other_links(&meta))
+      && !parent_directories(&self.to, &self.relative).iter().any(|dir| is_symlink(dir))
+  }
+
+  /// Updates the existing destination file in place, writing only changed blocks (see `delta`).
+  /// If a hasher is given, it's fed with the source's content.
+  pub fn execute_delta(&self, progress: &MultiProgress, worker_progress: &ProgressBar, hasher: Option<&mut blake3::Hasher>) -> std::io::Result<Written> {
+    let file_progress = progress.add(ProgressBar::new(self.bytes));
+    file_progress.set_style(
+      ProgressStyle::with_template("Delta: {wide_bar} {bytes} / {total_bytes}")
+      .unwrap()
+      .progress_chars(PROGERSS_BAR_FILE)
+    );
+    let worker_start_pos = worker_progress.position();
+
+    let src_metadata = fs::metadata(&self.from)?;
+    let res = delta::update_in_place(&self.from, &self.to, hasher, |processed| {
+      file_progress.set_position(processed);
+      worker_progress.set_position(processed + worker_start_pos);
+    }).map(|bytes| Written{
+      bytes,
+      skipped_holes: false,
+      metadata_error: metadata::apply(&self.from, &src_metadata, &self.to, self.preserve).err(),
+    });
+
+    file_progress.finish_and_clear();
+    progress.remove(&file_progress);
+    res
+  }
+
+  /// Re-reads the destination from disk and compares its content to the given hash of the source.
+  pub fn verify(&self, source_hash: &blake3::Hash) -> std::io::Result<bool> {
+    drop_cache(&self.to);
+    Ok(hash_file(&self.to)? == *source_hash)
+  }
+
+  /// Removes the destination after it failed verification, so the next run copies it again, whatever the compare mode.
+  /// If it can't be removed, it's marked as outdated by resetting its mtime instead.
+  pub fn invalidate(&self) {
+    if fs::remove_file(&self.to).is_err() {
+      let _ = filetime::set_file_mtime(&self.to, FileTime::zero());
+    }
+  }
+
+  /// Returns how many bytes of an interrupted copy in the temporary file can be kept, with a hasher fed with them:
+  /// its sidecar has to match the source, and the copied prefix has to hash equal to the source's.
+  fn resumable_bytes(&self, temp: &Path, source: &ResumeInfo) -> Option<(u64, blake3::Hasher)> {
+    let copied = fs::metadata(temp).map(|m| m.len()).unwrap_or(0);
+    if copied == 0 || copied > source.bytes
+      || ResumeInfo::read(&sidecar_path(temp)).is_none_or(|info| info != *source) {
+      return None;
+    }
+    match (hash_file_prefix(&self.from, copied), hash_file(temp)) {
+      (Ok(src_hasher), Ok(temp_hash)) if src_hasher.finalize() == temp_hash => Some((copied, src_hasher)),
+      _ => None,
+    }
+  }
 
+  fn copy_with_progress(&self, temp: &Path, file_progress: &ProgressBar, worker_progress: &ProgressBar, mut hasher: Option<&mut blake3::Hasher>) -> std::io::Result<Written> {
     let mut reader = fs::File::open(&self.from)?;
-    let mut writer = fs::File::create(&self.to)?;
-    // 1MiB buffer is too big for stack (1MiB total stack size...)
-    // let mut buffer = [0u8; 1024*1024];
-    // 4MiB vector-buffer lives on heap, better performance overall
-    let mut buffer = vec![0u8; 1024 * 1024 * 4];
-    let mut copied: u64 = 0;
+    let src_metadata = reader.metadata()?;
+    let source = ResumeInfo::from_metadata(&src_metadata);
+    let sidecar = sidecar_path(temp);
+
+    file_progress.set_message("Verifying");
+    let resumable = self.resumable_bytes(temp, &source);
+    let resumed = resumable.as_ref().map_or(0, |(copied, _)| *copied);
+    let mut writer = if let Some((_, prefix_hasher)) = resumable {
+      file_progress.set_message("Resuming");
+      if let Some(hasher) = hasher.as_deref_mut() {
+        *hasher = prefix_hasher;
+      }
+      fs::OpenOptions::new().write(true).open(temp)?
+    } else {
+      file_progress.set_message("Copying");
+      let writer = fs::File::create(temp)?;
+      fs::write(&sidecar, serde_json::to_string(&source).unwrap())?;
+      writer
+    };
+    let is_sparse = sparse::is_sparse(&src_metadata);
     let worker_start_pos = worker_progress.position();
+    file_progress.set_position(resumed);
+    worker_progress.set_position(resumed + worker_start_pos);
 
-    loop {
-      let num_bytes = reader.read(&mut buffer)?;
-      if num_bytes == 0 {break;}
-      writer.write_all(&buffer[..num_bytes])?;
-      copied += num_bytes as u64;
+    let written = copy_sparse(&mut reader, &mut writer, resumed, source.bytes, is_sparse, hasher, |copied, written| {
+      if is_sparse {
+        file_progress.set_message(format!("Sparse ({} written)", bytes_to_string(written)));
+      }
       file_progress.set_position(copied);
       worker_progress.set_position(copied + worker_start_pos);
+    })?;
+
+    let metadata_error = self.commit(writer, temp, &src_metadata)?;
+    let _ = fs::remove_file(&sidecar);
+    Ok(Written{metadata_error, ..written})
+  }
+}
+
+
+
+pub struct Link {
+  pub from: PathBuf,
+  pub to: PathBuf,
+  pub relative: String,
+  pub bytes: u64,
+  /// Used if the file has to be copied instead.
+  pub preserve: Preserve,
+}
+
+impl Link {
+  pub fn new(from: PathBuf, to: PathBuf, relative: String, bytes: u64, preserve: Preserve) -> Self {
+    Self{from, to, relative, bytes, preserve}
+  }
+
+  /// Creates a hard link to an existing file.
+  /// Falls back to copying the file if the file system doesn't support hard links.
+  pub fn execute(&self) -> std::io::Result<()> {
+    create_parent_directories(&self.to, &self.relative)?;
+    fs::hard_link(&self.from, &self.to).or_else(|_| Copy::new(
+      self.from.clone(),
+      self.to.clone(),
+      self.relative.clone(),
+      self.bytes,
+      self.preserve
+    ).execute(None).map(|_| ()))
+  }
+}
+
+
+
+/// Recreates a hard link between files of the source: links the destination to the destination of another link
+/// of the same file. Sent after all copies, so the other link's destination is up to date.
+pub struct HardLink {
+  /// Destination of the first link of the file.
+  pub first: PathBuf,
+  pub to: PathBuf,
+  pub relative: String,
+}
+
+impl HardLink {
+  pub fn new(first: PathBuf, to: PathBuf, relative: String) -> Self {
+    Self{first, to, relative}
+  }
+
+  /// Returns true if the destination already is a link to the same file as the first link's destination.
+  pub fn is_linked(&self) -> bool {
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::MetadataExt;
+      match (fs::metadata(&self.first), fs::symlink_metadata(&self.to)) {
+        (Ok(first), Ok(to)) => first.dev() == to.dev() && first.ino() == to.ino(),
+        _ => false,
+      }
+    }
+    #[cfg(not(unix))]
+    false
+  }
+
+  /// Moves an existing destination file into the trash directory, before it gets replaced.
+  pub fn move_existing_to_trash(&self, trash: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(&self.to).is_ok_and(|meta| !meta.is_dir()) {
+      move_file(&self.to, &trash.join(&self.relative))
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Creates the link, replacing a file at the destination. The link is created under a temporary name first:
+  /// if it can't be (eg. the first link's copy failed), the existing file is kept.
+  pub fn execute(&self) -> std::io::Result<()> {
+    create_parent_directories(&self.to, &self.relative)?;
+    let temp = temp_path(&self.to);
+    let _ = fs::remove_file(&temp);
+    fs::hard_link(&self.first, &temp)?;
+    let res = if fs::symlink_metadata(&self.to).is_ok_and(|meta| meta.is_dir()) {
+      Err(io::Error::new(io::ErrorKind::IsADirectory, format!("{} is a directory", self.to.display())))
+    } else {
+      fs::rename(&temp, &self.to)
     };
+    if res.is_err() {
+      let _ = fs::remove_file(&temp);
+    }
+    res
+  }
+}
 
-    self.copy_mtime();
-    file_progress.finish_and_clear();
-    progress.remove(&file_progress);
-    Ok(())
+/// Updates the metadata of an unchanged file whose preserved metadata differs from the source.
+pub struct UpdateMetadata {
+  pub from: PathBuf,
+  pub to: PathBuf,
+  pub relative: String,
+  pub preserve: Preserve,
+  /// Content hash of the unchanged file, if known; kept in the index.
+  pub hash: Option<blake3::Hash>,
+}
+
+impl UpdateMetadata {
+  pub fn new(from: PathBuf, to: PathBuf, relative: String, preserve: Preserve, hash: Option<blake3::Hash>) -> Self {
+    Self{from, to, relative, preserve, hash}
   }
+
+  pub fn execute(&self) -> std::io::Result<()> {
+    metadata::apply(&self.from, &fs::metadata(&self.from)?, &self.to, self.preserve)
+  }
+}
+
+
+
+/// Recreates a symlink of the source in the target.
+pub struct Symlink {
+  /// The source link; only used to tell whether it points to a directory (on Windows).
+  pub from: PathBuf,
+  /// Where the link points to, as read from the source link.
+  pub target: PathBuf,
+  pub to: PathBuf,
+  pub relative: String,
 }
 
+impl Symlink {
+  pub fn new(from: PathBuf, target: PathBuf, to: PathBuf, relative: String) -> Self {
+    Self{from, target, to, relative}
+  }
+
+  /// Moves an existing destination file or link into the trash directory, before it gets replaced.
+  pub fn move_existing_to_trash(&self, trash: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(&self.to).is_ok_and(|meta| !meta.is_dir()) {
+      move_file(&self.to, &trash.join(&self.relative))
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Creates the link, replacing a file or link at the destination. Never replaces directories.
+  pub fn execute(&self) -> std::io::Result<()> {
+    create_parent_directories(&self.to, &self.relative)?;
+    if fs::symlink_metadata(&self.to).is_ok_and(|meta| !meta.is_dir()) {
+      fs::remove_file(&self.to)?;
+    }
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(&self.target, &self.to);
+    #[cfg(windows)]
+    return if fs::metadata(&self.from).is_ok_and(|meta| meta.is_dir()) {
+      std::os::windows::fs::symlink_dir(&self.target, &self.to)
+    } else {
+      std::os::windows::fs::symlink_file(&self.target, &self.to)
+    };
+  }
+}
 
 
 pub struct Delete {
@@ -89,4 +557,218 @@ impl Delete {
   pub fn new(path: PathBuf, relative: String) -> Self {
     Self{path, relative}
   }
-}
\ No newline at end of file
+
+  /// Deletes the file or, if a trash directory is given, moves it there.
+  pub fn execute(&self, trash: Option<&Path>) -> std::io::Result<()> {
+    match trash {
+      Some(trash) => move_file(&self.path, &trash.join(&self.relative)),
+      None => fs::remove_file(&self.path),
+    }
+  }
+}
+
+
+/// Temporary file left behind by an interrupted copy.
+/// Sent after all copies, so files still being written are never removed.
+pub struct Cleanup {
+  pub path: PathBuf,
+  pub relative: String,
+}
+
+impl Cleanup {
+  pub fn new(path: PathBuf, relative: String) -> Self {
+    Self{path, relative}
+  }
+
+  /// Removes the temporary file, if it wasn't renamed into place by a copy in the meantime.
+  pub fn execute(&self) -> std::io::Result<()> {
+    match fs::remove_file(&self.path) {
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+      res => res,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::test_dir::TestDir;
+
+  fn copy_to(to: PathBuf) -> Copy {
+    Copy::new(PathBuf::new(), to, String::new(), 0, Preserve::default())
+  }
+
+  #[test]
+  fn updates_only_regular_files_in_place() {
+    let dir = TestDir::new("in-place");
+    fs::write(dir.join("file"), b"content").unwrap();
+
+    assert!(copy_to(dir.join("file")).can_update_in_place());
+    assert!(!copy_to(dir.join("missing")).can_update_in_place());
+    assert!(!copy_to(dir.to_path_buf()).can_update_in_place());
+    #[cfg(unix)]
+    {
+      std::os::unix::fs::symlink(dir.join("file"), dir.join("link")).unwrap();
+      assert!(!copy_to(dir.join("link")).can_update_in_place());
+    }
+  }
+
+  #[test]
+  #[cfg(unix)]
+  fn keeps_hard_links_apart_from_the_updated_file() {
+    let dir = TestDir::new("hard-links");
+    fs::write(dir.join("a"), vec![1u8; 4000]).unwrap();
+    fs::hard_link(dir.join("a"), dir.join("b")).unwrap();
+    let mut src = vec![1u8; 4000];
+    src[1000..1010].fill(2);
+    fs::write(dir.join("src"), &src).unwrap();
+
+    // a delta update would change "b" along with "a"; a copy replaces "a" only
+    let copy = Copy::new(dir.join("src"), dir.join("a"), String::from("a"), 4000, Preserve::default());
+    assert!(!copy.can_update_in_place());
+    copy.execute(None).unwrap();
+    let (a, b) = (fs::read(dir.join("a")).unwrap(), fs::read(dir.join("b")).unwrap());
+    assert!(a == src);
+    assert!(b == vec![1u8; 4000]);
+  }
+
+  #[test]
+  #[cfg(unix)]
+  fn replaces_symlinked_parent_directories() {
+    let dir = TestDir::new("linked-parent");
+    fs::create_dir_all(dir.join("outside")).unwrap();
+    fs::create_dir_all(dir.join("target")).unwrap();
+    fs::write(dir.join("outside/inner"), b"outside").unwrap();
+    std::os::unix::fs::symlink(dir.join("outside"), dir.join("target/link")).unwrap();
+    fs::write(dir.join("src"), b"source").unwrap();
+
+    let copy = Copy::new(dir.join("src"), dir.join("target/link/inner"), String::from("link/inner"), 6, Preserve::default());
+    assert!(!copy.can_update_in_place());
+    copy.execute(None).unwrap();
+    assert!(fs::symlink_metadata(dir.join("target/link")).unwrap().is_dir());
+    assert_eq!(fs::read(dir.join("target/link/inner")).unwrap(), b"source");
+    assert_eq!(fs::read(dir.join("outside/inner")).unwrap(), b"outside");
+  }
+
+  #[test]
+  fn keeps_the_file_if_the_hard_link_fails() {
+    let dir = TestDir::new("hard-link-failed");
+    fs::write(dir.join("b"), b"previous").unwrap();
+    // the first link's copy failed: there is nothing to link to
+    let link = HardLink::new(dir.join("a"), dir.join("b"), String::from("b"));
+    assert!(link.execute().is_err());
+    assert_eq!(fs::read(dir.join("b")).unwrap(), b"previous");
+    assert_eq!(fs::read_dir(&*dir).unwrap().count(), 1);
+
+    fs::write(dir.join("a"), b"current").unwrap();
+    link.execute().unwrap();
+    assert_eq!(fs::read(dir.join("b")).unwrap(), b"current");
+    assert!(link.is_linked() || cfg!(not(unix)));
+    assert_eq!(fs::read_dir(&*dir).unwrap().count(), 2);
+  }
+
+  #[test]
+  fn keeps_holes_of_sparse_files() {
+    let dir = TestDir::new("sparse-copy");
+    let len = 4 * 1024 * 1024;
+    let mut file = fs::File::create(dir.join("src")).unwrap();
+    file.set_len(len).unwrap();
+    file.seek(SeekFrom::Start(len / 2)).unwrap();
+    file.write_all(b"data").unwrap();
+    drop(file);
+    let src_sparse = sparse::is_sparse(&fs::metadata(dir.join("src")).unwrap());
+
+    let mut hasher = blake3::Hasher::new();
+    let copy = Copy::new(dir.join("src"), dir.join("dst"), String::from("dst"), len, Preserve::default());
+    let written = copy.execute(Some(&mut hasher)).unwrap();
+    let (src, dst) = (fs::read(dir.join("src")).unwrap(), fs::read(dir.join("dst")).unwrap());
+    let dst_sparse = sparse::is_sparse(&fs::metadata(dir.join("dst")).unwrap());
+
+    assert!(dst == src);
+    assert_eq!(hasher.finalize(), blake3::hash(&src));
+    assert_eq!(written.skipped_holes, src_sparse && written.bytes < len);
+    if written.skipped_holes {
+      assert!(dst_sparse);
+    }
+  }
+
+  /// Deterministic, incompressible test data.
+  fn data(len: usize) -> Vec<u8> {
+    let mut data = vec![0u8; len];
+    blake3::Hasher::new().update(b"resume").finalize_xof().fill(&mut data);
+    data
+  }
+
+  /// Writes a source file and an interrupted copy of its first `copied` bytes, with a sidecar describing the source.
+  fn interrupted_copy(dir: &Path, src: &[u8], copied: usize) -> (Copy, PathBuf, ResumeInfo) {
+    fs::write(dir.join("src"), src).unwrap();
+    let copy = Copy::new(dir.join("src"), dir.join("dst"), String::from("dst"), src.len() as u64, Preserve::default());
+    let temp = copy.temp_path();
+    fs::write(&temp, &src[..copied]).unwrap();
+    let source = ResumeInfo::from_metadata(&fs::metadata(dir.join("src")).unwrap());
+    fs::write(sidecar_path(&temp), serde_json::to_string(&source).unwrap()).unwrap();
+    (copy, temp, source)
+  }
+
+  #[test]
+  fn resumes_after_the_copied_prefix() {
+    let dir = TestDir::new("resume");
+    let src = data(100_000);
+    let (copy, temp, source) = interrupted_copy(&dir, &src, 40_000);
+
+    let (copied, mut prefix_hasher) = copy.resumable_bytes(&temp, &source).unwrap();
+    assert_eq!(copied, 40_000);
+    prefix_hasher.update(&src[40_000..]);
+    assert_eq!(prefix_hasher.finalize(), blake3::hash(&src));
+
+    // the whole copy, resumed from there
+    let mut hasher = blake3::Hasher::new();
+    let hidden = ProgressBar::hidden();
+    copy.copy_with_progress(&temp, &hidden, &hidden, Some(&mut hasher)).unwrap();
+    assert!(fs::read(dir.join("dst")).unwrap() == src);
+    assert_eq!(hasher.finalize(), blake3::hash(&src));
+    assert!(!temp.exists() && !sidecar_path(&temp).exists());
+  }
+
+  #[test]
+  fn starts_over_if_the_source_changed() {
+    let dir = TestDir::new("resume-changed");
+    let src = data(100_000);
+    let (copy, temp, source) = interrupted_copy(&dir, &src, 40_000);
+
+    // grown, with an equal prefix
+    let mut grown = src.clone();
+    grown.extend_from_slice(b"more");
+    fs::write(dir.join("src"), &grown).unwrap();
+    let grown_info = ResumeInfo::from_metadata(&fs::metadata(dir.join("src")).unwrap());
+    assert!(copy.resumable_bytes(&temp, &grown_info).is_none());
+
+    // same size, but modified since
+    fs::write(dir.join("src"), &src).unwrap();
+    filetime::set_file_mtime(dir.join("src"), FileTime::from_unix_time(source.mtime + 60, 0)).unwrap();
+    let touched = ResumeInfo::from_metadata(&fs::metadata(dir.join("src")).unwrap());
+    assert!(copy.resumable_bytes(&temp, &touched).is_none());
+
+    // the copy starts over and still ends up equal
+    let hidden = ProgressBar::hidden();
+    copy.copy_with_progress(&temp, &hidden, &hidden, None).unwrap();
+    assert!(fs::read(dir.join("dst")).unwrap() == src);
+  }
+
+  #[test]
+  fn starts_over_if_the_copied_prefix_is_corrupt() {
+    let dir = TestDir::new("resume-corrupt");
+    let src = data(100_000);
+    let (copy, temp, source) = interrupted_copy(&dir, &src, 40_000);
+    let mut corrupt = src[..40_000].to_vec();
+    corrupt[20_000] ^= 0xff;
+    fs::write(&temp, &corrupt).unwrap();
+
+    assert!(copy.resumable_bytes(&temp, &source).is_none());
+    let mut hasher = blake3::Hasher::new();
+    let hidden = ProgressBar::hidden();
+    copy.copy_with_progress(&temp, &hidden, &hidden, Some(&mut hasher)).unwrap();
+    assert!(fs::read(dir.join("dst")).unwrap() == src);
+    assert_eq!(hasher.finalize(), blake3::hash(&src));
+  }
+}
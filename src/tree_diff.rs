@@ -0,0 +1,224 @@
+use std::{fs, io, path::{Path, PathBuf}};
+
+use serde::Serialize;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::{
+  compare::{compare_metadata, Comparison, FileState},
+  config::{compare_mode::CompareMode, json::JSONConfig, symlink_mode::SymlinkMode, sync_mode::SyncMode},
+  delta,
+  index::META_DIR,
+  filter::Filter,
+  scanner::walk_source,
+  snapshot::latest_snapshot,
+  task_copy_delete::is_temp_file,
+  util::hash_file::hash_file
+};
+
+/// How a file differs between source and target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Change {
+  /// Only in the source.
+  New,
+  /// In both, but different (according to the compare mode).
+  Modified,
+  /// Only in the target; deleted from the source.
+  Deleted,
+  /// In the source, but excluded by the rules. Directories skipped as a whole are one entry, their path ending in `/`.
+  Excluded,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Difference {
+  pub change: Change,
+  pub path: String,
+  pub bytes: u64,
+}
+
+/// Result of comparing a source directory to its target.
+#[derive(Debug, Default)]
+pub struct TreeDiff {
+  /// Number of source files compared (all included files).
+  pub compared: u64,
+  pub differences: Vec<Difference>,
+  /// Errors reading either directory; differences may be incomplete if there are any.
+  pub errors: Vec<String>,
+}
+
+impl TreeDiff {
+  /// Returns true if a sync wouldn't copy or delete anything.
+  pub fn in_sync(&self) -> bool {
+    self.differences.iter().all(|d| d.change == Change::Excluded)
+  }
+
+  pub fn count(&self, change: Change) -> usize {
+    self.differences.iter().filter(|d| d.change == change).count()
+  }
+}
+
+/// A source and target to compare, with everything needed to compare them like a sync would.
+pub struct TreePair {
+  /// Name of the JSON config operation, if any.
+  pub name: Option<String>,
+  pub source: PathBuf,
+  /// Target directory; the newest snapshot for snapshot-mode operations.
+  pub target: PathBuf,
+  pub filter: Filter,
+  pub compare: CompareMode,
+  pub symlinks: SymlinkMode,
+  pub backup_dir: Option<PathBuf>,
+}
+
+impl TreePair {
+  /// Resolves the pairs to compare: a single source/target pair (without rules) if a target is given, otherwise
+  /// all operations (or the named one) of the JSON config at `source`. Also returns errors for operations that
+  /// can't be compared.
+  pub fn resolve(source: &Path, target: Option<PathBuf>, operation: Option<&str>) -> (Vec<TreePair>, Vec<String>) {
+    let mut pairs = Vec::new();
+    let mut errors = Vec::new();
+    if let Some(target) = target {
+      pairs.push(TreePair {
+        name: None,
+        source: source.to_path_buf(),
+        target,
+        filter: Filter::default(),
+        compare: CompareMode::default(),
+        symlinks: SymlinkMode::default(),
+        backup_dir: None,
+      });
+      return (pairs, errors);
+    }
+
+    for op in JSONConfig::read(source).take_operations(operation) {
+      let Some(target) = &op.target else { continue };
+      let compared = if op.mode.unwrap_or_default() == SyncMode::Snapshot {
+        match latest_snapshot(target) {
+          Some(snapshot) => snapshot,
+          None => {
+            errors.push(format!("No snapshot found in {}", target.display()));
+            continue;
+          }
+        }
+      } else {
+        target.clone()
+      };
+      let filter = match Filter::new(&op.rules) {
+        Ok(filter) => filter,
+        Err(err) => {
+          errors.push(err.to_string());
+          continue;
+        }
+      };
+      pairs.push(TreePair {
+        filter,
+        backup_dir: op.backup_dir.as_ref().map(|dir| target.join(dir)),
+        compare: op.compare.unwrap_or_default(),
+        symlinks: op.symlinks.unwrap_or_default(),
+        name: op.name,
+        source: op.source,
+        target: compared,
+      });
+    }
+    (pairs, errors)
+  }
+}
+
+/// Compares a target to its source like the scanner does, without copying or deleting anything.
+/// Like mirroring, files are only "deleted" if they're missing in the source; excluded source files are
+/// left alone in the target. Content is only hashed if the compare mode can't decide by metadata.
+/// Preserved symlinks are compared by where they point.
+pub fn diff_trees(pair: &TreePair, compare: CompareMode) -> TreeDiff {
+  let (src, dst) = (pair.source.as_path(), pair.target.as_path());
+  let mut diff = TreeDiff::default();
+  let interrupted = delta::interrupted(dst);
+
+  let mut ignore_files = pair.filter.ignore_files();
+  // ignored files and excluded directories aren't walked: each is reported as one excluded entry
+  let mut pruned = Vec::new();
+  let on_pruned = |entry: &DirEntry| pruned.push((entry.path().to_path_buf(), entry.file_type().is_dir()));
+  for entry in walk_source(src, &pair.filter, pair.symlinks, &mut ignore_files, on_pruned, |_| {}) {
+    let entry = match entry {
+      Ok(entry) => entry,
+      // followed links that are broken are skipped by syncs
+      Err(err) if pair.symlinks.follows() && err.path().is_some_and(|path| path.is_symlink() && !path.exists()) => continue,
+      Err(err) => { diff.errors.push(err.to_string()); continue; }
+    };
+    let relative_path = entry.path().strip_prefix(src).unwrap();
+    let path_in_dst = dst.join(relative_path);
+    let src_metadata = match entry.metadata() {
+      Ok(metadata) => metadata,
+      Err(err) => { diff.errors.push(err.to_string()); continue; }
+    };
+    let bytes = src_metadata.len();
+    let path = relative_path.display().to_string();
+
+    if !pair.filter.is_included(relative_path, Some(&src_metadata)) {
+      diff.differences.push(Difference{change: Change::Excluded, path, bytes});
+      continue;
+    }
+    diff.compared += 1;
+
+    let change = match fs::symlink_metadata(&path_in_dst) {
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Some(Change::New),
+      Err(err) => { diff.errors.push(format!("{}: {}", path_in_dst.display(), err)); continue; }
+      // a preserved link is recreated if it points somewhere else
+      Ok(_) if entry.path_is_symlink() && !pair.symlinks.follows() =>
+        (fs::read_link(entry.path()).ok() != fs::read_link(&path_in_dst).ok()).then_some(Change::Modified),
+      // a link in the target is replaced by the source's file
+      Ok(dst_metadata) if dst_metadata.is_symlink() => Some(Change::Modified),
+      // partially updated by an interrupted delta update
+      Ok(_) if interrupted.contains(&path) => Some(Change::Modified),
+      Ok(dst_metadata) => match compare_metadata(compare, &FileState::from(&src_metadata), &FileState::from(&dst_metadata)) {
+        Comparison::Equal => None,
+        Comparison::Different => Some(Change::Modified),
+        Comparison::Ambiguous => match (hash_file(entry.path()), hash_file(&path_in_dst)) {
+          (Ok(src_hash), Ok(dst_hash)) => (src_hash != dst_hash).then_some(Change::Modified),
+          (Err(err), _) | (_, Err(err)) => { diff.errors.push(format!("{}: {}", path, err)); continue; }
+        }
+      }
+    };
+    if let Some(change) = change {
+      diff.differences.push(Difference{change, path, bytes});
+    }
+  }
+
+  for (path, is_dir) in pruned {
+    let relative = path.strip_prefix(src).unwrap().display().to_string();
+    let (path, bytes) = if is_dir {
+      let bytes = WalkDir::new(&path).into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum();
+      (format!("{}/", relative), bytes)
+    } else {
+      (relative, fs::symlink_metadata(&path).map(|m| m.len()).unwrap_or(0))
+    };
+    diff.differences.push(Difference{change: Change::Excluded, path, bytes});
+  }
+
+  diff.errors.extend(ignore_files.errors.into_iter().map(|err| format!("Error in ignore file: {}", err)));
+
+  for entry in WalkDir::new(dst).into_iter()
+    .filter_entry(|e| (e.depth() != 1 || e.file_name() != META_DIR) && Some(e.path()) != pair.backup_dir.as_deref()) {
+    let entry = match entry {
+      Ok(entry) => entry,
+      Err(err) => { diff.errors.push(err.to_string()); continue; }
+    };
+    let file_type = entry.file_type();
+    if !(file_type.is_file() || file_type.is_symlink()) || is_temp_file(entry.file_name()) { continue; }
+    let relative_path = entry.path().strip_prefix(dst).unwrap();
+    // broken symlinks in the source still exist
+    if fs::symlink_metadata(src.join(relative_path)).is_err() {
+      diff.differences.push(Difference{
+        change: Change::Deleted,
+        path: relative_path.display().to_string(),
+        bytes: entry.metadata().map(|m| m.len()).unwrap_or(0),
+      });
+    }
+  }
+
+  diff
+}
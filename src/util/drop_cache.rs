@@ -0,0 +1,14 @@
+use std::path::Path;
+
+/// Asks the OS to evict a file's (already written) pages from the page cache, so reading it again reads from the disk.
+/// Only implemented on Linux; no-op elsewhere.
+pub fn drop_cache(path: &Path) {
+  #[cfg(target_os = "linux")]
+  if let Ok(file) = std::fs::File::open(path) {
+    use std::os::fd::AsRawFd;
+    // SAFETY: the file descriptor is valid as long as `file` lives; fadvise is only a hint.
+    unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED); }
+  }
+  #[cfg(not(target_os = "linux"))]
+  let _ = path;
+}
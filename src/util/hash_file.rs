@@ -0,0 +1,16 @@
+use std::{fs::File, io::{self, Read}, path::Path};
+
+/// Reads a file and returns the BLAKE3 hash of its content.
+pub fn hash_file(path: &Path) -> io::Result<blake3::Hash> {
+  let mut hasher = blake3::Hasher::new();
+  hasher.update_reader(File::open(path)?)?;
+  Ok(hasher.finalize())
+}
+
+/// Reads the first `len` bytes of a file (or less, if the file is shorter) into a BLAKE3 hasher,
+/// which can be finalized or continue with the rest of the file.
+pub fn hash_file_prefix(path: &Path, len: u64) -> io::Result<blake3::Hasher> {
+  let mut hasher = blake3::Hasher::new();
+  hasher.update_reader(File::open(path)?.take(len))?;
+  Ok(hasher)
+}
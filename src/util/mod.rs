@@ -1,3 +1,10 @@
 pub mod bytes_to_string;
+pub mod drop_cache;
+pub mod hash_file;
+pub mod move_file;
 pub mod normalize_drive;
-pub mod run_command;
\ No newline at end of file
+pub mod run_command;
+pub mod sparse;
+#[cfg(test)]
+pub mod test_dir;
+pub mod timestamp;
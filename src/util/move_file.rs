@@ -0,0 +1,18 @@
+use std::{fs, io, path::Path};
+
+use filetime::FileTime;
+
+/// Moves a file, creating missing parent directories of the destination.
+/// If renaming fails (eg. across file systems), the file is copied and the original removed.
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+  if let Some(parent) = to.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  if fs::rename(from, to).is_ok() {
+    return Ok(());
+  }
+  let metadata = fs::metadata(from)?;
+  fs::copy(from, to)?;
+  let _ = filetime::set_file_mtime(to, FileTime::from_last_modification_time(&metadata));
+  fs::remove_file(from)
+}
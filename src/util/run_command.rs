@@ -15,7 +15,7 @@ const FLAG: &str = "-c";
 /// Returns the exit code of the executed command.
 pub fn run_command<S: AsRef<str>>(cmd: S) -> i32 {
   let status = Command::new(SHELL)
-    .args([FLAG, format!("{}", cmd.as_ref()).as_ref()])
+    .args([FLAG, cmd.as_ref()])
     .stdout(Stdio::inherit())
     .stderr(Stdio::inherit())
     .status()
@@ -0,0 +1,93 @@
+use std::{fs::{File, Metadata}, io};
+
+/// Returns true if a file has fewer blocks allocated than its size needs, i.e. it has holes.
+/// Always false on other platforms than Unix.
+pub fn is_sparse(metadata: &Metadata) -> bool {
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::MetadataExt;
+    metadata.blocks() * 512 < metadata.len()
+  }
+  #[cfg(not(unix))]
+  {
+    let _ = metadata;
+    false
+  }
+}
+
+/// Returns the regions (start and end offset) of a file holding data, from `offset` up to `len`; the rest are holes.
+/// Only implemented on Linux (SEEK_DATA/SEEK_HOLE); elsewhere, or if the file system doesn't support it,
+/// the whole range is one region.
+pub fn data_regions(file: &File, offset: u64, len: u64) -> io::Result<Vec<(u64, u64)>> {
+  #[cfg(target_os = "linux")]
+  {
+    use std::os::fd::AsRawFd;
+    let seek = |offset: u64, whence: libc::c_int| {
+      // SAFETY: the file descriptor is valid as long as `file` lives. Moves the file's offset; copies seek before reading.
+      match unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) } {
+        -1 => Err(io::Error::last_os_error()),
+        pos => Ok(pos as u64),
+      }
+    };
+    let mut regions = Vec::new();
+    let mut pos = offset;
+    while pos < len {
+      let start = match seek(pos, libc::SEEK_DATA) {
+        Ok(start) => start,
+        // no data after this offset: the rest is a hole
+        Err(err) if err.raw_os_error() == Some(libc::ENXIO) => break,
+        Err(err) if err.raw_os_error() == Some(libc::EINVAL) => return Ok(vec![(offset, len)]),
+        Err(err) => return Err(err),
+      };
+      if start >= len {
+        break;
+      }
+      let end = seek(start, libc::SEEK_HOLE)?.min(len);
+      regions.push((start, end));
+      pos = end;
+    }
+    Ok(regions)
+  }
+  #[cfg(not(target_os = "linux"))]
+  {
+    let _ = file;
+    Ok(vec![(offset, len)])
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::test_dir::TestDir;
+  use std::io::{Seek, SeekFrom, Write};
+
+  const MIB: u64 = 1024 * 1024;
+
+  #[test]
+  fn finds_data_between_holes() {
+    let dir = TestDir::new("sparse");
+    let path = dir.join("file");
+    // a hole, one block of data, and another hole up to the end
+    let mut file = File::create(&path).unwrap();
+    file.set_len(3 * MIB).unwrap();
+    file.seek(SeekFrom::Start(MIB)).unwrap();
+    file.write_all(&[1u8; 4096]).unwrap();
+    file.sync_all().unwrap();
+    let metadata = file.metadata().unwrap();
+
+    let regions = data_regions(&file, 0, 3 * MIB).unwrap();
+    let from_data_end = data_regions(&file, MIB + 4096, 3 * MIB).unwrap();
+    let below_len = data_regions(&file, 0, MIB).unwrap();
+    drop(file);
+
+    // the data is always covered, whether the file system supports holes or not
+    assert!(regions.iter().any(|&(start, end)| start <= MIB && end >= MIB + 4096));
+    assert!(regions.iter().all(|&(start, end)| start < end && end <= 3 * MIB));
+    if is_sparse(&metadata) && regions.len() == 1 && regions[0] != (0, 3 * MIB) {
+      let (start, end) = regions[0];
+      assert!(start > 0 && end < 3 * MIB, "holes at both ends are skipped: {:?}", regions);
+      assert!(from_data_end.is_empty(), "nothing after the data: {:?}", from_data_end);
+      assert!(below_len.is_empty(), "nothing before the data: {:?}", below_len);
+    }
+  }
+}
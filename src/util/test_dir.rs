@@ -0,0 +1,30 @@
+use std::{fs, ops::Deref, path::{Path, PathBuf}, sync::atomic::{AtomicUsize, Ordering}};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A fresh directory below the system's temp directory for a test; removed again on drop, also if the test fails.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+  pub fn new(name: &str) -> Self {
+    let path = std::env::temp_dir().join(format!(
+      "baccy-test-{}-{}-{}", name, std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    Self(path)
+  }
+}
+
+impl Deref for TestDir {
+  type Target = Path;
+  fn deref(&self) -> &Path {
+    &self.0
+  }
+}
+
+impl Drop for TestDir {
+  fn drop(&mut self) {
+    let _ = fs::remove_dir_all(&self.0);
+  }
+}
@@ -0,0 +1,9 @@
+use chrono::Local;
+
+/// Format of timestamps used in directory names; sorts chronologically and is valid on all file systems.
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+/// Returns the current local time, formatted for use in directory names.
+pub fn timestamp() -> String {
+  Local::now().format(TIMESTAMP_FORMAT).to_string()
+}
@@ -0,0 +1,131 @@
+use std::{path::{Path, PathBuf}, process};
+
+use colored::Colorize;
+use indicatif::ProgressBar;
+use serde::Serialize;
+
+use crate::{
+  config::compare_mode::CompareMode,
+  progress_helpers::setup_spinner,
+  tree_diff::{diff_trees, Change, TreeDiff, TreePair}
+};
+
+/// Exit code if any target is out of sync with its source.
+pub const EXIT_OUT_OF_SYNC: i32 = 3;
+/// Exit code if files couldn't be read; takes precedence, as the comparison is incomplete.
+pub const EXIT_IO_ERROR: i32 = 4;
+
+/// Machine-readable result of verifying one operation.
+#[derive(Serialize)]
+struct Report<'a> {
+  name: Option<&'a str>,
+  source: &'a Path,
+  target: &'a Path,
+  compared: u64,
+  in_sync: bool,
+  missing: Vec<&'a str>,
+  differing: Vec<&'a str>,
+  extra: Vec<&'a str>,
+  errors: &'a [String],
+}
+
+impl<'a> Report<'a> {
+  fn new(name: Option<&'a str>, source: &'a Path, target: &'a Path, diff: &'a TreeDiff) -> Self {
+    let paths = |change: Change| diff.differences.iter()
+      .filter(|d| d.change == change)
+      .map(|d| d.path.as_str())
+      .collect();
+    Self {
+      name,
+      source,
+      target,
+      compared: diff.compared,
+      in_sync: diff.in_sync() && diff.errors.is_empty(),
+      missing: paths(Change::New),
+      differing: paths(Change::Modified),
+      extra: paths(Change::Deleted),
+      errors: &diff.errors,
+    }
+  }
+}
+
+/// Runs the "verify" subcommand: compares targets to their sources without modifying anything.
+/// Either verifies a single source/target pair, or all operations (or the named one) of a JSON config.
+/// Exits with 0 if all targets are in sync, or with `EXIT_OUT_OF_SYNC` / `EXIT_IO_ERROR`.
+pub fn verify_command(source: &Path, target: Option<PathBuf>, operation: Option<String>, checksum: bool, json: bool) {
+  let (pairs, errors) = TreePair::resolve(source, target, operation.as_deref());
+
+  let mut in_sync = true;
+  let mut io_error = !errors.is_empty();
+  let mut diffs: Vec<TreeDiff> = Vec::new();
+  for pair in &pairs {
+    let compare = if checksum {CompareMode::Checksum} else {pair.compare};
+    if !json {
+      println!();
+      println!("{}", format!("Verify:   {} → {}", pair.source.to_str().unwrap().cyan(), pair.target.to_str().unwrap().cyan()).bold());
+    }
+    let mut progress = if json {ProgressBar::hidden()} else {ProgressBar::new_spinner()};
+    setup_spinner(&mut progress, "Comparing files...");
+    let diff = diff_trees(pair, compare);
+    progress.finish_and_clear();
+
+    in_sync &= diff.in_sync();
+    io_error |= !diff.errors.is_empty();
+    if !json {
+      print_diff(pair.name.as_deref(), &diff);
+    }
+    diffs.push(diff);
+  }
+
+  if json {
+    let reports: Vec<Report> = pairs.iter().zip(&diffs)
+      .map(|(pair, diff)| Report::new(pair.name.as_deref(), &pair.source, &pair.target, diff))
+      .collect();
+    println!("{}", serde_json::json!({
+      "in_sync": in_sync && !io_error,
+      "operations": reports,
+      "errors": errors,
+    }));
+  } else {
+    for err in &errors {
+      println!("{}", err.bright_red());
+    }
+    println!();
+    if io_error {
+      println!("{}", " Errors occurred, verification is incomplete! ".on_red().bold());
+    } else if !in_sync {
+      println!("{}", " Target out of sync! ".on_yellow().bold());
+    } else {
+      println!("All targets in sync.");
+    }
+  }
+
+  if io_error {
+    process::exit(EXIT_IO_ERROR);
+  } else if !in_sync {
+    process::exit(EXIT_OUT_OF_SYNC);
+  }
+}
+
+fn print_diff(name: Option<&str>, diff: &TreeDiff) {
+  for difference in &diff.differences {
+    let label = match difference.change {
+      Change::New => "MISSING",
+      Change::Modified => "DIFFERS",
+      Change::Deleted => "EXTRA",
+      Change::Excluded => continue,
+    };
+    println!("{:>10}: {}", label.dimmed().bold(), difference.path.dimmed());
+  }
+  for err in &diff.errors {
+    println!("{:>10}: {}", "ERROR".bright_red().bold(), err);
+  }
+  println!("{} {}Compared {} files: {} missing, {} differing, {} extra.",
+    "▪▪▪".blue(),
+    name.map(|n| format!("{}: ", n)).unwrap_or_default(),
+    diff.compared.to_string().cyan(),
+    diff.count(Change::New).to_string().cyan(),
+    diff.count(Change::Modified).to_string().cyan(),
+    diff.count(Change::Deleted).to_string().cyan()
+  );
+}